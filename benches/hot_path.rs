@@ -0,0 +1,153 @@
+//! SQE cache and `user_data` allocator throughput.
+//!
+//! Each group runs the ring's fixed-capacity structures next to a model of the
+//! `RefCell<Vec<_>>` free lists they replaced, under the same access pattern:
+//! fill the structure, then drain it. The lists are measured both fresh, as
+//! the old ring created them with `Vec::new()` at setup and grew them on
+//! first use, and warmed up with their capacity reserved.
+//!
+//! Run with `cargo bench --bench hot_path`.
+
+use core::cell::RefCell;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use io_urine::{io_uring_sqe, IoUring};
+
+const ENTRIES: u32 = 256;
+
+// The previous allocator: a monotonic counter plus a growable free list
+struct VecUserData {
+    next: u64,
+    free: RefCell<Vec<u64>>,
+}
+
+impl VecUserData {
+    fn alloc(&mut self) -> u64 {
+        if let Some(reused) = self.free.borrow_mut().pop() {
+            return reused;
+        }
+        self.next += 1;
+        self.next
+    }
+
+    fn free(&self, user_data: u64) {
+        self.free.borrow_mut().push(user_data);
+    }
+}
+
+fn user_data(c: &mut Criterion) {
+    let mut group = c.benchmark_group("user_data");
+
+    let ring = IoUring::with_entries(ENTRIES, ENTRIES).expect("Failed to create ring");
+    let mut values = Vec::with_capacity(ring.user_data_capacity());
+    group.bench_function("slab", |b| {
+        b.iter(|| {
+            while let Some(value) = ring.alloc_user_data() {
+                values.push(value);
+            }
+            for value in values.drain(..) {
+                ring.free_user_data(black_box(value));
+            }
+        });
+    });
+
+    let capacity = ring.user_data_capacity();
+    group.bench_function("refcell_vec_fresh", |b| {
+        // A fresh free list each batch, as after setup
+        b.iter_batched(
+            || VecUserData {
+                next: 0,
+                free: RefCell::new(Vec::new()),
+            },
+            |mut alloc| {
+                for _ in 0..capacity {
+                    values.push(alloc.alloc());
+                }
+                for value in values.drain(..) {
+                    alloc.free(black_box(value));
+                }
+                alloc
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    // Reserved up front and warmed by every iteration before the measured
+    // ones, so both sides run in steady state
+    let mut alloc = VecUserData {
+        next: 0,
+        free: RefCell::new(Vec::with_capacity(capacity)),
+    };
+    group.bench_function("refcell_vec_warm", |b| {
+        b.iter(|| {
+            for _ in 0..capacity {
+                values.push(alloc.alloc());
+            }
+            for value in values.drain(..) {
+                alloc.free(black_box(value));
+            }
+        });
+    });
+
+    group.finish();
+}
+
+fn sqe_cache(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sqe_cache");
+
+    let mut ring = IoUring::with_entries(ENTRIES, ENTRIES).expect("Failed to create ring");
+    let mut sqes: Vec<*mut io_uring_sqe> = Vec::with_capacity(ENTRIES as usize);
+    while let Some(sqe) = ring.get_sqe() {
+        sqes.push(sqe);
+    }
+
+    group.bench_function("index_list", |b| {
+        b.iter(|| {
+            for &sqe in &sqes {
+                // SAFETY: the SQEs were never submitted
+                ring.reclaim_sqe(unsafe { &mut *sqe });
+            }
+            // The SQ itself is full, so this stops once the cache is empty
+            while let Some(sqe) = ring.get_sqe_with_reclaim() {
+                black_box(sqe);
+            }
+        });
+    });
+
+    group.bench_function("refcell_vec_fresh", |b| {
+        b.iter_batched(
+            || RefCell::new(Vec::new()),
+            |cache: RefCell<Vec<*mut io_uring_sqe>>| {
+                for &sqe in &sqes {
+                    // SAFETY: as above
+                    unsafe { *sqe = io_uring_sqe::default() };
+                    cache.borrow_mut().push(sqe);
+                }
+                while let Some(sqe) = cache.borrow_mut().pop() {
+                    black_box(sqe);
+                }
+                cache
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    let cache: RefCell<Vec<*mut io_uring_sqe>> = RefCell::new(Vec::with_capacity(sqes.len()));
+    group.bench_function("refcell_vec_warm", |b| {
+        b.iter(|| {
+            for &sqe in &sqes {
+                // SAFETY: as above
+                unsafe { *sqe = io_uring_sqe::default() };
+                cache.borrow_mut().push(sqe);
+            }
+            while let Some(sqe) = cache.borrow_mut().pop() {
+                black_box(sqe);
+            }
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, user_data, sqe_cache);
+criterion_main!(benches);
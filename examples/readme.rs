@@ -17,7 +17,9 @@ fn main() {
     println!("Waited for 1 completion");
 
     if !ring.is_cq_empty() {
-        ring.copy_cqes(1);
+        let mut cqes = [io_urine::io_uring_cqe::default(); 1];
+        let copied = ring.copy_cqes(&mut cqes);
+        println!("Reaped {} completions", copied);
     }
 
     println!("Example completed successfully!");
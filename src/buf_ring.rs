@@ -0,0 +1,429 @@
+//! Ring-mapped provided buffers.
+//!
+//! A [`BufRing`] registers a buffer group with `IORING_REGISTER_PBUF_RING`
+//! (Linux 5.19+). Instead of queueing `IORING_OP_PROVIDE_BUFFERS` to hand
+//! buffers to the kernel, the application writes them into a ring shared with
+//! the kernel and publishes them by moving the ring's tail. Operations queued
+//! with `IOSQE_BUFFER_SELECT` and the group's id, such as
+//! [`RecvMulti`](crate::sqe::RecvMulti), pick a buffer from the ring when data
+//! arrives and report its id in the CQE flags.
+//!
+//! [`BufRing::get`] turns such a CQE back into a [`Buf`], which puts the
+//! buffer back on the ring when dropped.
+//!
+//! A ring registered with [`BufRing::new_incremental`] (Linux 6.12+) has the
+//! kernel consume its buffers bit by bit: each completion takes only the
+//! bytes it needs and, while the buffer has room left, sets
+//! `IORING_CQE_F_BUF_MORE` and keeps it for the next one. Every such CQE gets
+//! a `Buf` over its own part of the buffer, and the buffer is recycled once
+//! the kernel is done with it and every one of those `Buf`s is gone. Those
+//! parts are only tracked on the application side, so each CQE must reach
+//! `get` exactly once.
+
+use core::cell::Cell;
+use core::ffi::c_void;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use alloc::boxed::Box;
+use rustix::fd::OwnedFd;
+use rustix::io::Errno;
+use rustix::io_uring::IoringRegisterOp;
+use rustix::mm::{MapFlags, ProtFlags};
+
+use crate::err::InitError;
+use crate::io_uring::IoUring;
+use crate::{io_uring_cqe, PbufRingEntry, PbufRingReg};
+
+// Largest ring the kernel accepts
+const MAX_ENTRIES: u16 = 1 << 15;
+
+/// A provided buffer group backed by a ring shared with the kernel
+///
+/// Holds `entries` buffers of `buf_len` bytes each, with ids `0..entries`.
+/// Every buffer starts out on the ring. The ring keeps a duplicate of the
+/// `io_uring` fd so it can unregister itself when dropped, even after the
+/// `IoUring` is gone. If the ring fd was closed with
+/// [`IoUring::close_ring_fd`], or the kernel refuses to unregister the group,
+/// the memory is leaked on drop instead, as the kernel may still write to it.
+pub struct BufRing {
+    fd: Option<OwnedFd>,
+    ring: NonNull<PbufRingEntry>,
+    ring_size: usize,
+    bufs: NonNull<u8>,
+    bufs_size: usize,
+    buf_len: u32,
+    entries: u16,
+    bgid: u16,
+    incremental: bool,
+    // Local copy of the tail; the shared one is only ever written from here
+    tail: Cell<u16>,
+    // Indexed by buffer id
+    slots: Box<[Slot]>,
+}
+
+// What the application knows of a buffer that left the ring
+#[derive(Default)]
+struct Slot {
+    // Bytes already handed out, with incremental consumption
+    consumed: Cell<u32>,
+    // Live `Buf`s pointing into the buffer
+    live: Cell<u32>,
+    // The kernel is done with the buffer, so it goes back on the ring once
+    // `live` drops to zero
+    done: Cell<bool>,
+}
+
+// SAFETY: the mappings are owned by the `BufRing` and move with it. The
+// kernel only touches ring entries between its head and the tail, which
+// synchronizes them.
+unsafe impl Send for BufRing {}
+
+fn map_anonymous(size: usize) -> Result<NonNull<c_void>, InitError> {
+    // SAFETY: a fresh private mapping doesn't alias anything
+    let addr = unsafe {
+        rustix::mm::mmap_anonymous(
+            core::ptr::null_mut(),
+            size,
+            ProtFlags::READ | ProtFlags::WRITE,
+            MapFlags::PRIVATE,
+        )
+    }
+    .map_err(InitError::MmapFailed)?;
+    NonNull::new(addr).ok_or(InitError::MmapFailed(Errno::INVAL))
+}
+
+impl BufRing {
+    /// Register buffer group `bgid` with `entries` buffers of `buf_len` bytes
+    ///
+    /// `entries` must be a power of two no larger than 32768.
+    ///
+    /// ## Errors
+    /// Returns `InitError::InvalidParameters` for a bad `entries` or a zero
+    /// `buf_len`, `InitError::MmapFailed` if the memory can't be mapped, or
+    /// `InitError::RegisterFailed` if the kernel rejects the ring, e.g. when
+    /// `bgid` is already in use.
+    pub fn new(ring: &IoUring, bgid: u16, entries: u16, buf_len: u32) -> Result<Self, InitError> {
+        Self::register(ring, bgid, entries, buf_len, 0)
+    }
+
+    /// Register buffer group `bgid` for incremental consumption
+    /// (`IOU_PBUF_RING_INC`)
+    ///
+    /// Suits large buffers: a completion only uses up as many bytes as it
+    /// received, and the rest of the buffer serves the next ones.
+    ///
+    /// ## Errors
+    /// As for [`new`](Self::new). Kernels before 6.12 reject the flag with
+    /// `InitError::RegisterFailed(Errno::INVAL)`.
+    pub fn new_incremental(
+        ring: &IoUring,
+        bgid: u16,
+        entries: u16,
+        buf_len: u32,
+    ) -> Result<Self, InitError> {
+        Self::register(ring, bgid, entries, buf_len, crate::IOU_PBUF_RING_INC)
+    }
+
+    fn register(
+        ring: &IoUring,
+        bgid: u16,
+        entries: u16,
+        buf_len: u32,
+        flags: u16,
+    ) -> Result<Self, InitError> {
+        if !entries.is_power_of_two() || entries > MAX_ENTRIES || buf_len == 0 {
+            return Err(InitError::InvalidParameters);
+        }
+        let ring_size = usize::from(entries) * core::mem::size_of::<PbufRingEntry>();
+        let bufs_size = usize::from(entries)
+            .checked_mul(buf_len as usize)
+            .ok_or(InitError::InvalidParameters)?;
+
+        let ring_ptr = map_anonymous(ring_size)?.cast::<PbufRingEntry>();
+        let bufs = match map_anonymous(bufs_size) {
+            Ok(bufs) => bufs.cast::<u8>(),
+            Err(e) => {
+                // SAFETY: mapped just above and not shared yet
+                let _ = unsafe { rustix::mm::munmap(ring_ptr.as_ptr().cast(), ring_size) };
+                return Err(e);
+            }
+        };
+
+        let reg = PbufRingReg {
+            ring_addr: ring_ptr.as_ptr() as u64,
+            ring_entries: u32::from(entries),
+            bgid,
+            flags,
+            ..Default::default()
+        };
+        if let Err(e) = ring.register_pbuf_ring(&reg) {
+            // SAFETY: the kernel never saw either mapping
+            unsafe {
+                let _ = rustix::mm::munmap(ring_ptr.as_ptr().cast(), ring_size);
+                let _ = rustix::mm::munmap(bufs.as_ptr().cast(), bufs_size);
+            }
+            return Err(e);
+        }
+
+        let this = Self {
+            // Unregistering is best effort, so a failed dup only costs the
+            // memory on drop
+            fd: ring
+                .ring_fd()
+                .and_then(|fd| rustix::io::fcntl_dupfd_cloexec(fd, 0).ok()),
+            ring: ring_ptr,
+            ring_size,
+            bufs,
+            bufs_size,
+            buf_len,
+            entries,
+            bgid,
+            incremental: flags & crate::IOU_PBUF_RING_INC != 0,
+            tail: Cell::new(0),
+            slots: (0..entries).map(|_| Slot::default()).collect(),
+        };
+        for bid in 0..entries {
+            this.push(bid);
+        }
+        this.publish();
+        Ok(this)
+    }
+
+    /// Buffer group id to select buffers from, as in
+    /// [`RecvMulti::new`](crate::sqe::RecvMulti::new)
+    #[must_use]
+    pub fn bgid(&self) -> u16 {
+        self.bgid
+    }
+
+    /// Number of buffers in the group
+    #[must_use]
+    pub fn entries(&self) -> u16 {
+        self.entries
+    }
+
+    /// Size of each buffer in bytes
+    #[must_use]
+    pub fn buf_len(&self) -> u32 {
+        self.buf_len
+    }
+
+    /// Number of buffers held by live [`Buf`]s
+    #[must_use]
+    pub fn taken(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.live.get() > 0).count()
+    }
+
+    /// Whether the ring was registered with [`new_incremental`](Self::new_incremental)
+    #[must_use]
+    pub fn is_incremental(&self) -> bool {
+        self.incremental
+    }
+
+    /// The data a CQE of this group received, in the buffer it was given
+    ///
+    /// The buffer goes back on the ring once the returned [`Buf`] is
+    /// dropped. On an incremental ring, a CQE flagged
+    /// `IORING_CQE_F_BUF_MORE` leaves the buffer with the kernel: the `Buf`
+    /// covers just the `res` bytes after those handed out for earlier CQEs,
+    /// and the buffer is only recycled after the CQE without the flag.
+    ///
+    /// Returns `None` if the CQE carries no buffer (`IORING_CQE_F_BUFFER`
+    /// unset), names a buffer id outside the group, or names a buffer the
+    /// kernel already finished with and that hasn't been recycled yet.
+    ///
+    /// # Safety
+    /// `cqe` must have been posted by the kernel for an operation that
+    /// selected its buffer from this ring's group: the CQE doesn't say which
+    /// group its buffer came from, and for any other the kernel may still be
+    /// writing to the buffer named. Nor may it be passed again once that
+    /// buffer went back on the ring.
+    ///
+    /// On an incremental ring, every CQE flagged `IORING_CQE_F_BUF_MORE` must
+    /// be passed exactly once, and in the order the kernel posted them: the
+    /// part each `Buf` covers follows from the CQEs passed before, so a
+    /// missing, repeated or foreign one hands out bytes the kernel has yet to
+    /// fill.
+    #[must_use]
+    pub unsafe fn get(&self, cqe: &io_uring_cqe) -> Option<Buf<'_>> {
+        if cqe.flags & crate::IORING_CQE_F_BUFFER == 0 {
+            return None;
+        }
+        let bid = (cqe.flags >> crate::IORING_CQE_BUFFER_SHIFT) as u16;
+        let slot = self.slots.get(usize::from(bid))?;
+        if slot.done.get() {
+            return None;
+        }
+
+        let offset = slot.consumed.get() as usize;
+        let len = usize::try_from(cqe.res)
+            .unwrap_or(0)
+            .min(self.buf_len as usize - offset);
+        if self.incremental && cqe.flags & crate::IORING_CQE_F_BUF_MORE != 0 {
+            slot.consumed.set((offset + len) as u32);
+        } else {
+            slot.done.set(true);
+        }
+        slot.live.set(slot.live.get() + 1);
+
+        Some(Buf {
+            ring: self,
+            bid,
+            offset,
+            len,
+        })
+    }
+
+    fn buf_ptr(&self, bid: u16) -> *mut u8 {
+        // SAFETY: `bid < entries`, so this stays inside the mapping
+        unsafe {
+            self.bufs
+                .as_ptr()
+                .add(usize::from(bid) * self.buf_len as usize)
+        }
+    }
+
+    /// Write buffer `bid` into the next ring slot, without publishing it
+    fn push(&self, bid: u16) {
+        let tail = self.tail.get();
+        let mask = self.entries - 1;
+        // SAFETY: the slot is within the ring, and past the tail the kernel
+        // last saw, so it doesn't read it. `resv` is left alone: in the first
+        // entry it is the shared tail.
+        unsafe {
+            let entry = self.ring.as_ptr().add(usize::from(tail & mask));
+            (*entry).addr = self.buf_ptr(bid) as u64;
+            (*entry).len = self.buf_len;
+            (*entry).bid = bid;
+        }
+        self.tail.set(tail.wrapping_add(1));
+    }
+
+    /// Hand every pushed buffer to the kernel
+    fn publish(&self) {
+        // SAFETY: the tail overlays `resv` of the first entry, a naturally
+        // aligned u16 that the kernel never writes
+        let tail = unsafe { &*core::ptr::addr_of!((*self.ring.as_ptr()).resv).cast::<AtomicU16>() };
+        tail.store(self.tail.get(), Ordering::Release);
+    }
+
+    /// Drop a `Buf`'s hold on buffer `bid`, recycling it if that was the last
+    fn release(&self, bid: u16) {
+        let slot = &self.slots[usize::from(bid)];
+        slot.live.set(slot.live.get() - 1);
+        if slot.live.get() == 0 && slot.done.get() {
+            slot.done.set(false);
+            slot.consumed.set(0);
+            self.push(bid);
+            self.publish();
+        }
+    }
+}
+
+impl Drop for BufRing {
+    fn drop(&mut self) {
+        let Some(fd) = &self.fd else {
+            return;
+        };
+        let reg = PbufRingReg {
+            bgid: self.bgid,
+            ..Default::default()
+        };
+        // SAFETY: the kernel only reads `reg` during the call
+        let unregistered = unsafe {
+            rustix::io_uring::io_uring_register(
+                fd,
+                IoringRegisterOp::UnregisterPbufRing,
+                core::ptr::from_ref(&reg).cast::<c_void>(),
+                1,
+            )
+        };
+        // Unmapping a group the kernel still has would let it write to
+        // whatever is mapped there next, so a failure leaks the memory
+        if unregistered.is_ok() {
+            // SAFETY: both were mapped in `new`, and the kernel is done with them
+            unsafe {
+                let _ = rustix::mm::munmap(self.ring.as_ptr().cast(), self.ring_size);
+                let _ = rustix::mm::munmap(self.bufs.as_ptr().cast(), self.bufs_size);
+            }
+        }
+    }
+}
+
+impl core::fmt::Debug for BufRing {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BufRing")
+            .field("bgid", &self.bgid)
+            .field("entries", &self.entries)
+            .field("buf_len", &self.buf_len)
+            .field("incremental", &self.incremental)
+            .field("taken", &self.taken())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A buffer picked by the kernel from a [`BufRing`]
+///
+/// Derefs to the bytes the operation filled in. Dropping it puts the buffer
+/// back on the ring, unless the kernel still consumes it incrementally or
+/// other `Buf`s point into it.
+pub struct Buf<'a> {
+    ring: &'a BufRing,
+    bid: u16,
+    offset: usize,
+    len: usize,
+}
+
+impl Buf<'_> {
+    /// Buffer id within the group
+    #[must_use]
+    pub fn bid(&self) -> u16 {
+        self.bid
+    }
+
+    /// Where the data starts in the buffer; non-zero only on incremental
+    /// rings
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl Deref for Buf<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the kernel is done with these bytes until the buffer is
+        // recycled, and no other `Buf` covers them
+        unsafe {
+            core::slice::from_raw_parts(self.ring.buf_ptr(self.bid).add(self.offset), self.len)
+        }
+    }
+}
+
+impl DerefMut for Buf<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as for `deref`
+        unsafe {
+            core::slice::from_raw_parts_mut(self.ring.buf_ptr(self.bid).add(self.offset), self.len)
+        }
+    }
+}
+
+impl Drop for Buf<'_> {
+    fn drop(&mut self) {
+        self.ring.release(self.bid);
+    }
+}
+
+impl core::fmt::Debug for Buf<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Buf")
+            .field("bid", &self.bid)
+            .field("offset", &self.offset)
+            .field("len", &self.len)
+            .finish()
+    }
+}
@@ -0,0 +1,172 @@
+//! Kernel version and capability detection.
+//!
+//! The kernel version alone is a poor guide: distributions backport `io_uring`
+//! features, and opcodes can be compiled out. [`Capabilities`] therefore
+//! answers each question from the most direct evidence available, the
+//! `IORING_FEAT_*` bits and the opcode probe first, and falls back to the
+//! version only for behavior neither of them reports.
+
+use crate::io_uring::Probe;
+
+/// Running kernel version as `(major, minor, patch)`, from `uname`
+///
+/// Returns `(0, 0, 0)` if the release string can't be parsed.
+#[must_use]
+pub fn kernel_version() -> (u32, u32, u32) {
+    let uname = rustix::system::uname();
+    uname
+        .release()
+        .to_str()
+        .ok()
+        .and_then(parse_release)
+        .unwrap_or((0, 0, 0))
+}
+
+/// Parse a release string such as `6.8.0-45-generic`
+pub(crate) fn parse_release(release: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = release.split(['.', '-', '+']).map(|part| {
+        let digits = part.bytes().take_while(u8::is_ascii_digit).count();
+        part[..digits].parse::<u32>().ok()
+    });
+
+    let major = parts.next()??;
+    let minor = parts.next().flatten().unwrap_or(0);
+    let patch = parts.next().flatten().unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+/// A feature the running kernel may or may not offer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Capability {
+    /// `IORING_OP_READ_FIXED`/`WRITE_FIXED` with registered buffers
+    FixedBuffers,
+    /// Kernel-side SQ polling without registered files (`FEAT_SQPOLL_NONFIXED`)
+    SqPoll,
+    /// Timeouts passed to `io_uring_enter` (`FEAT_EXT_ARG`)
+    ExtArg,
+    /// Internal polling instead of io-wq for pollable files (`FEAT_FAST_POLL`)
+    FastPoll,
+    /// Overflowed CQEs are kept instead of dropped (`FEAT_NODROP`)
+    NoDrop,
+    /// `IORING_SETUP_SQE128` and `IORING_SETUP_CQE32`
+    ExtendedFormats,
+    /// Multishot `IORING_OP_POLL_ADD`
+    MultishotPoll,
+    /// Multishot `IORING_OP_ACCEPT`
+    MultishotAccept,
+    /// Multishot `IORING_OP_RECV`
+    MultishotRecv,
+    /// Provided buffer rings (`IORING_REGISTER_PBUF_RING`)
+    BufferRing,
+    /// Incrementally consumed provided buffer rings (`IOU_PBUF_RING_INC`)
+    IncrementalBufferRing,
+    /// Registered ring fds (`IORING_REGISTER_RING_FDS`)
+    RegisteredRingFd,
+    /// `IORING_SETUP_SINGLE_ISSUER`
+    SingleIssuer,
+    /// `IORING_SETUP_DEFER_TASKRUN`
+    DeferTaskrun,
+    /// `IORING_SETUP_NO_MMAP`
+    NoMmap,
+    /// Zero-copy `IORING_OP_SEND_ZC`
+    SendZc,
+}
+
+impl Capability {
+    /// Every capability, in declaration order
+    pub const ALL: [Self; 16] = [
+        Self::FixedBuffers,
+        Self::SqPoll,
+        Self::ExtArg,
+        Self::FastPoll,
+        Self::NoDrop,
+        Self::ExtendedFormats,
+        Self::MultishotPoll,
+        Self::MultishotAccept,
+        Self::MultishotRecv,
+        Self::BufferRing,
+        Self::IncrementalBufferRing,
+        Self::RegisteredRingFd,
+        Self::SingleIssuer,
+        Self::DeferTaskrun,
+        Self::NoMmap,
+        Self::SendZc,
+    ];
+}
+
+/// What the running kernel supports, combining its version, the ring's
+/// `IORING_FEAT_*` bits and an opcode probe
+///
+/// Obtained from [`IoUring::capabilities`](crate::IoUring::capabilities).
+#[derive(Debug)]
+pub struct Capabilities {
+    version: (u32, u32, u32),
+    features: u32,
+    // `None` on kernels without IORING_REGISTER_PROBE (before 5.6)
+    probe: Option<Probe>,
+}
+
+impl Capabilities {
+    pub(crate) fn new(version: (u32, u32, u32), features: u32, probe: Option<Probe>) -> Self {
+        Self {
+            version,
+            features,
+            probe,
+        }
+    }
+
+    /// Kernel version as `(major, minor, patch)`
+    #[must_use]
+    pub fn kernel_version(&self) -> (u32, u32, u32) {
+        self.version
+    }
+
+    fn at_least(&self, major: u32, minor: u32) -> bool {
+        (self.version.0, self.version.1) >= (major, minor)
+    }
+
+    fn feature(&self, feature: u32) -> bool {
+        self.features & feature != 0
+    }
+
+    fn opcode(&self, opcode: u8) -> bool {
+        self.probe
+            .as_ref()
+            .is_some_and(|probe| probe.opcode_supported(opcode))
+    }
+
+    /// Whether the kernel offers `capability`
+    #[must_use]
+    pub fn has(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::FixedBuffers => {
+                self.opcode(crate::IORING_OP_READ_FIXED)
+                    || (self.probe.is_none() && self.at_least(5, 1))
+            }
+            Capability::SqPoll => self.feature(crate::IORING_FEAT_SQPOLL_FIXED),
+            Capability::ExtArg => self.feature(crate::IORING_FEAT_EXT_ARG),
+            Capability::FastPoll => self.feature(crate::IORING_FEAT_FAST_POLL),
+            Capability::NoDrop => self.feature(crate::IORING_FEAT_NODROP),
+            Capability::MultishotPoll => {
+                self.opcode(crate::IORING_OP_POLL_ADD) && self.at_least(5, 13)
+            }
+            Capability::MultishotAccept => {
+                self.opcode(crate::IORING_OP_ACCEPT) && self.at_least(5, 19)
+            }
+            Capability::MultishotRecv => self.opcode(crate::IORING_OP_RECV) && self.at_least(6, 0),
+            Capability::ExtendedFormats | Capability::BufferRing => self.at_least(5, 19),
+            Capability::IncrementalBufferRing => self.at_least(6, 12),
+            Capability::RegisteredRingFd => self.at_least(5, 18),
+            Capability::SingleIssuer => self.at_least(6, 0),
+            Capability::DeferTaskrun => self.at_least(6, 1),
+            Capability::NoMmap => self.at_least(6, 5),
+            Capability::SendZc => self.opcode(crate::IORING_OP_SEND_ZC),
+        }
+    }
+
+    /// All capabilities the kernel offers
+    pub fn supported(&self) -> impl Iterator<Item = Capability> + '_ {
+        Capability::ALL.into_iter().filter(|&cap| self.has(cap))
+    }
+}
@@ -0,0 +1,268 @@
+//! Linked operation chains.
+//!
+//! A [`Chain`] queues its entries back to back with `IOSQE_IO_LINK` (or
+//! `IOSQE_IO_HARDLINK`) on every entry but the last, so each one starts only
+//! after the previous one completed. When an entry fails, or the optional
+//! link timeout fires, the kernel completes the rest with `-ECANCELED`. The
+//! whole chain is queued or none of it is; a full SQ never splits it.
+//!
+//! A chain's entries get `user_data` values of their own, in a reserved part
+//! of the `user_data` space: the top byte is `0xfe`, the next 8 bits are
+//! zero, then come 32 bits numbering the chain and 16 bits indexing the
+//! entry. Keep other `user_data` values, e.g. [`Token`](crate::Token) kinds,
+//! out of it. Chain numbers only repeat after 2^32 chains, so CQEs of a
+//! chain whose handle was dropped aren't taken for a later chain's.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::err::{ChainError, EnterError};
+use crate::io_uring::IoUring;
+use crate::sqe::{Entry, LinkTimeout};
+use crate::{io_uring_cqe, PrepSqe};
+
+// Top byte of every chain entry's `user_data`, next to the one of resource
+// tags
+const MARK: u64 = 0xfe << 56;
+// Bits holding the entry index
+const INDEX_BITS: u32 = 16;
+
+/// Builder for a chain of linked entries
+///
+/// Obtained from [`IoUring::chain`]. Each entry's `user_data` is replaced
+/// with one numbering the chain and the entry (see the
+/// [module docs](crate::chain)), which the [`ChainHandle`] uses to recognize the
+/// chain's CQEs.
+pub struct Chain<'a> {
+    ring: &'a mut IoUring,
+    entries: Vec<Entry>,
+    link: u8,
+    timeout: Option<crate::Timespec>,
+}
+
+impl<'a> Chain<'a> {
+    pub(crate) fn new(ring: &'a mut IoUring) -> Self {
+        Self {
+            ring,
+            entries: Vec::new(),
+            link: crate::IOSQE_IO_LINK,
+            timeout: None,
+        }
+    }
+
+    /// Append an entry to the chain
+    #[must_use]
+    pub fn push(mut self, entry: Entry) -> Self {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Link with `IOSQE_IO_HARDLINK`, so a failed entry doesn't cancel the
+    /// ones after it
+    #[must_use]
+    pub fn hardlinked(mut self) -> Self {
+        self.link = crate::IOSQE_IO_HARDLINK;
+        self
+    }
+
+    /// Cancel the last entry if it hasn't completed within `ts`
+    ///
+    /// Appends an `IORING_OP_LINK_TIMEOUT` entry. The timeout covers the
+    /// last entry only, as the kernel applies it to the entry just before
+    /// it.
+    #[must_use]
+    pub fn with_timeout(mut self, ts: crate::Timespec) -> Self {
+        self.timeout = Some(ts);
+        self
+    }
+
+    /// Queue the whole chain without submitting it
+    ///
+    /// ## Errors
+    /// Returns `ChainError::Empty` if no entry was pushed,
+    /// `ChainError::SkipSuccess` if an entry has `IOSQE_CQE_SKIP_SUCCESS`,
+    /// or `ChainError::QueueFull` if the SQ lacks room for every entry.
+    /// Nothing is queued in any of these cases.
+    pub fn queue(self) -> Result<ChainHandle, ChainError> {
+        self.enqueue().map(|(_, handle)| handle)
+    }
+
+    fn enqueue(self) -> Result<(&'a mut IoUring, ChainHandle), ChainError> {
+        let Self {
+            ring,
+            mut entries,
+            link,
+            timeout,
+        } = self;
+
+        // A lone link timeout has nothing to guard, and the kernel rejects it
+        if entries.is_empty() {
+            return Err(ChainError::Empty);
+        }
+        // The handle waits for every entry's CQE
+        if entries
+            .iter()
+            .any(|entry| entry.0.flags & crate::IOSQE_CQE_SKIP_SUCCESS != 0)
+        {
+            return Err(ChainError::SkipSuccess);
+        }
+
+        let timeout = timeout.map(Box::new);
+        let total = entries.len() + usize::from(timeout.is_some());
+        if (ring.sq_space_left() as usize) < total {
+            return Err(ChainError::QueueFull);
+        }
+
+        let base = MARK | u64::from(ring.next_chain()) << INDEX_BITS;
+        // The entry before a link timeout must be linked to it, so the last
+        // entry only goes unlinked when there's no timeout.
+        let linked = if timeout.is_some() {
+            entries.len()
+        } else {
+            entries.len() - 1
+        };
+        for (i, entry) in entries.iter_mut().enumerate() {
+            *entry = entry.user_data(base | i as u64);
+            if i < linked {
+                *entry = entry.flags(link);
+            }
+        }
+        if let Some(ts) = &timeout {
+            entries.push(
+                LinkTimeout::new(ts, 0)
+                    .build()
+                    .user_data(base | (total - 1) as u64),
+            );
+        }
+
+        if ring.push_multiple(&entries).is_err() {
+            return Err(ChainError::QueueFull);
+        }
+
+        let handle = ChainHandle {
+            base,
+            results: alloc::vec![None; total],
+            timeout,
+        };
+        Ok((ring, handle))
+    }
+
+    /// Queue the whole chain and submit it
+    ///
+    /// ## Errors
+    /// As for [`queue`](Self::queue), plus `ChainError::Enter` if the
+    /// submit fails. The chain stays queued in that case and goes out with
+    /// the next submit, but its results can no longer be collected.
+    pub fn submit(self) -> Result<ChainHandle, ChainError> {
+        let (ring, handle) = self.enqueue()?;
+        match ring.submit() {
+            Ok(_) => Ok(handle),
+            Err(e) => Err(ChainError::Enter(e)),
+        }
+    }
+}
+
+/// Results of a queued chain, gathered from its CQEs in chain order
+///
+/// Feed it CQEs with [`complete`](Self::complete), or let
+/// [`wait`](Self::wait) reap them.
+#[derive(Debug)]
+pub struct ChainHandle {
+    // `user_data` of the chain's entries, without the entry index
+    base: u64,
+    // One per entry, the link timeout last
+    results: Vec<Option<i32>>,
+    // Read by the kernel when it consumes the link timeout entry, possibly
+    // from an SQ poll thread, so it lives until the entry's CQE arrives
+    timeout: Option<Box<crate::Timespec>>,
+}
+
+impl Drop for ChainHandle {
+    fn drop(&mut self) {
+        // The kernel may still read the timespec of a chain that wasn't
+        // submitted, or not consumed yet, so it's leaked instead of freed
+        if self.timeout_result().is_none() {
+            if let Some(ts) = self.timeout.take() {
+                Box::leak(ts);
+            }
+        }
+    }
+}
+
+impl ChainHandle {
+    fn entries(&self) -> usize {
+        self.results.len() - usize::from(self.timeout.is_some())
+    }
+
+    /// Record `cqe` if it belongs to the chain
+    ///
+    /// Returns `false` for CQEs of other operations.
+    pub fn complete(&mut self, cqe: &io_uring_cqe) -> bool {
+        if cqe.user_data & !((1 << INDEX_BITS) - 1) != self.base {
+            return false;
+        }
+        let i = (cqe.user_data as u16) as usize;
+        match self.results.get_mut(i) {
+            Some(result @ None) => {
+                *result = Some(cqe.res);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether every entry, and the link timeout, has completed
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.results.iter().all(Option::is_some)
+    }
+
+    /// CQE results of the chained entries, in the order they were pushed
+    ///
+    /// `None` marks entries whose CQE hasn't arrived yet.
+    #[must_use]
+    pub fn results(&self) -> &[Option<i32>] {
+        &self.results[..self.entries()]
+    }
+
+    /// CQE result of the link timeout: `-ETIME` if it fired, `-ECANCELED`
+    /// or `-ENOENT` if the entry it guarded finished first
+    #[must_use]
+    pub fn timeout_result(&self) -> Option<i32> {
+        self.timeout.as_ref()?;
+        self.results.last().copied().flatten()
+    }
+
+    /// Index of the first entry that failed, the rest of the chain having
+    /// been cancelled because of it
+    #[must_use]
+    pub fn failed_at(&self) -> Option<usize> {
+        self.results()
+            .iter()
+            .position(|res| res.is_some_and(|res| res < 0))
+    }
+
+    /// Reap CQEs until the whole chain has completed
+    ///
+    /// CQEs of other operations are consumed too, and passed to `other`.
+    ///
+    /// ## Errors
+    /// Returns `EnterError` if waiting for completions fails.
+    pub fn wait(
+        &mut self,
+        ring: &mut IoUring,
+        mut other: impl FnMut(&io_uring_cqe),
+    ) -> Result<(), EnterError> {
+        while !self.is_complete() {
+            let Some(cqe) = ring.peek_cqe().copied() else {
+                ring.submit_and_wait(1)?;
+                continue;
+            };
+            ring.completion().advance(1);
+            if !self.complete(&cqe) {
+                other(&cqe);
+            }
+        }
+        Ok(())
+    }
+}
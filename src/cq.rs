@@ -1,8 +1,41 @@
+//! Completion queue ring.
+//!
+//! Every raw pointer held by [`CompletionQueue`] points into memory mapped
+//! from the ring fd and owned by the `IoUring` the queue belongs to. The
+//! queue never outlives that mapping: it is only handed out by reference,
+//! through `IoUring::completion` and `IoUring::split`. Fields shared with the
+//! kernel (`khead`, `ktail`, `kflags`, `koverflow`) are only touched
+//! atomically, and CQEs are only read for slots between our head and the
+//! tail published by the kernel, which it does not rewrite until the head is
+//! advanced past them.
+
 use core::sync::atomic::{AtomicU32, Ordering};
 
 use rustix::io_uring::io_cqring_offsets;
 
-use crate::io_uring_cqe;
+use crate::{io_uring_cqe, io_uring_cqe32, RsrcEvent};
+
+/// Overflow state of the completion queue
+///
+/// With `IORING_FEAT_NODROP`, CQEs that don't fit in the ring are kept in a
+/// kernel-side backlog and flushed into the ring by the next enter with
+/// `IORING_ENTER_GETEVENTS`. Without it, or if the kernel fails to allocate
+/// backlog space, they are dropped and only counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OverflowStatus {
+    /// CQEs are waiting in the kernel backlog
+    pub backlogged: bool,
+    /// Number of CQEs the kernel had to drop
+    pub dropped: u32,
+}
+
+impl OverflowStatus {
+    /// Whether every completion made it, or will make it, into the ring
+    #[must_use]
+    pub fn is_lossless(&self) -> bool {
+        self.dropped == 0
+    }
+}
 
 pub struct CompletionQueue {
     khead: *mut AtomicU32,
@@ -12,32 +45,92 @@ pub struct CompletionQueue {
     kflags: *const AtomicU32,
     koverflow: *const AtomicU32,
     cqe_ptr: *mut io_uring_cqe,
+    // log2 of the CQE size in units of `io_uring_cqe` (1 with CQE32)
+    cqe_shift: u32,
     head: AtomicU32,
     tail: AtomicU32,
+    // Published entries still to post their last CQE, shared with the SQ,
+    // when in-flight entries are capped
+    outstanding: Option<*const AtomicU32>,
+    // Number of the `IoUring` the queue belongs to, 0 for queues made by hand
+    ring_id: usize,
 }
 
+// SAFETY: the queue exclusively owns its view of the CQ ring (see the module
+// docs), so moving it to another thread is sound. It is not `Sync`; shared
+// access from several consumers would race on the head.
+unsafe impl Send for CompletionQueue {}
+
 impl CompletionQueue {
+    /// Create a view of a CQ ring
+    ///
+    /// # Safety
+    /// `cq_ptr` must point to the mapped CQ ring described by `offsets`, with
+    /// 32-byte CQEs if `cqe32` is set. The mapping must outlive the queue.
     #[must_use]
-    pub unsafe fn new(cq_ptr: *mut u8, offsets: &io_cqring_offsets) -> Self {
+    pub unsafe fn new(cq_ptr: *mut u8, offsets: &io_cqring_offsets, cqe32: bool) -> Self {
         let khead = unsafe { cq_ptr.add(offsets.head as usize) as *mut AtomicU32 };
         let ktail = unsafe { cq_ptr.add(offsets.tail as usize) as *const AtomicU32 };
         let kflags = unsafe { cq_ptr.add(offsets.flags as usize) as *const AtomicU32 };
         let koverflow = unsafe { cq_ptr.add(offsets.overflow as usize) as *const AtomicU32 };
         let cqe_ptr = unsafe { cq_ptr.add(offsets.cqes as usize) as *mut io_uring_cqe };
+        let kring_mask = unsafe { *cq_ptr.add(offsets.ring_mask as usize).cast::<u32>() };
+        let kring_entries = unsafe { *cq_ptr.add(offsets.ring_entries as usize).cast::<u32>() };
 
         Self {
             khead,
             ktail,
-            kring_mask: offsets.ring_mask,
-            kring_entries: offsets.ring_entries,
+            kring_mask,
+            kring_entries,
             kflags,
             koverflow,
             cqe_ptr,
+            cqe_shift: u32::from(cqe32),
             head: AtomicU32::new(0),
             tail: AtomicU32::new(0),
+            outstanding: None,
+            ring_id: 0,
         }
     }
 
+    /// Take reaped requests off `outstanding`, which must outlive the queue
+    pub(crate) fn set_outstanding(&mut self, outstanding: *const AtomicU32) {
+        self.outstanding = Some(outstanding);
+    }
+
+    pub(crate) fn set_ring_id(&mut self, ring_id: usize) {
+        self.ring_id = ring_id;
+    }
+
+    /// Number of the `IoUring` the queue belongs to, shared with its SQ, or
+    /// 0 if the queue was made with [`new`](Self::new)
+    pub(crate) fn ring_id(&self) -> usize {
+        self.ring_id
+    }
+
+    /// Take the requests ended by the `count` CQEs from `head` off
+    /// `outstanding`
+    ///
+    /// Only CQEs without `IORING_CQE_F_MORE` end a request. Resource tag
+    /// CQEs don't belong to any request, and are told apart by their
+    /// reserved `user_data`.
+    fn count_completed(&self, outstanding: *const AtomicU32, head: u32, count: u32) {
+        let done = (0..count)
+            .filter(|&i| {
+                // SAFETY: the CQEs up to the kernel tail are valid until the
+                // head moves past them
+                let cqe = unsafe { &*self.cqe_at(head.wrapping_add(i) & self.kring_mask) };
+                cqe.flags & crate::IORING_CQE_F_MORE == 0 && RsrcEvent::from_cqe(cqe).is_none()
+            })
+            .count() as u32;
+        // SAFETY: see `set_outstanding`
+        let _ = unsafe {
+            (*outstanding).fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                Some(n.saturating_sub(done))
+            })
+        };
+    }
+
     #[must_use]
     pub fn ring_mask(&self) -> u32 {
         self.kring_mask
@@ -48,6 +141,17 @@ impl CompletionQueue {
         self.kring_entries
     }
 
+    /// Whether the ring posts 32-byte CQEs
+    #[must_use]
+    pub fn is_cqe32(&self) -> bool {
+        self.cqe_shift != 0
+    }
+
+    /// Pointer to the CQE in slot `index`, accounting for the entry size
+    fn cqe_at(&self, index: u32) -> *mut io_uring_cqe {
+        unsafe { self.cqe_ptr.add((index as usize) << self.cqe_shift) }
+    }
+
     pub(crate) fn get_khead(&self) -> u32 {
         unsafe { (*self.khead).load(Ordering::Acquire) }
     }
@@ -82,10 +186,24 @@ impl CompletionQueue {
         }
 
         let index = head & self.kring_mask;
-        let cqe = unsafe { &*self.cqe_ptr.add(index as usize) };
+        let cqe = unsafe { &*self.cqe_at(index) };
         Some(cqe)
     }
 
+    /// Peek at the next 32-byte CQE
+    ///
+    /// Returns `None` if the queue is empty or the ring was not set up with
+    /// `IORING_SETUP_CQE32`.
+    #[must_use]
+    pub fn peek32(&self) -> Option<&io_uring_cqe32> {
+        if !self.is_cqe32() {
+            return None;
+        }
+
+        let cqe = self.peek()?;
+        Some(unsafe { &*core::ptr::from_ref(cqe).cast::<io_uring_cqe32>() })
+    }
+
     #[must_use]
     pub fn peek_mut(&mut self) -> Option<&mut io_uring_cqe> {
         let tail = self.tail.load(Ordering::Acquire);
@@ -96,12 +214,15 @@ impl CompletionQueue {
         }
 
         let index = head & self.kring_mask;
-        let cqe = unsafe { &mut *self.cqe_ptr.add(index as usize) };
+        let cqe = unsafe { &mut *self.cqe_at(index) };
         Some(cqe)
     }
 
     pub fn advance(&mut self, count: u32) {
         let head = self.head.load(Ordering::Relaxed);
+        if let Some(outstanding) = self.outstanding {
+            self.count_completed(outstanding, head, count);
+        }
         let new_head = head.wrapping_add(count);
         self.head.store(new_head, Ordering::Release);
         self.set_khead(new_head);
@@ -117,10 +238,37 @@ impl CompletionQueue {
         unsafe { (*self.koverflow).load(Ordering::Relaxed) }
     }
 
+    pub(crate) fn khead_ptr(&self) -> *const AtomicU32 {
+        self.khead
+    }
+
     pub(crate) fn cqe_ptr(&self) -> *mut io_uring_cqe {
         self.cqe_ptr
     }
 
+    /// Take a batch of all CQEs currently available in the ring
+    ///
+    /// The returned guard yields each CQE in ring order, wrapping around the
+    /// end of the CQE array as needed. The kernel head is advanced once, by
+    /// the number of entries consumed, when the guard is dropped.
+    pub fn completions(&mut self) -> Completions<'_> {
+        self.update_kernel_tail();
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        Completions {
+            cq: self,
+            head,
+            tail,
+        }
+    }
+
+    /// Copy up to `cqes.len()` available CQEs into `cqes` and consume them
+    ///
+    /// Returns the number of entries copied.
+    pub fn copy_cqes(&mut self, cqes: &mut [io_uring_cqe]) -> usize {
+        self.completions().copy_into(cqes)
+    }
+
     /// Check if a CQE has the MORE flag set (multi-shot operation)
     #[must_use]
     pub fn cqe_is_multishot(&self, cqe: &crate::io_uring_cqe) -> bool {
@@ -133,6 +281,13 @@ impl CompletionQueue {
         cqe.flags & crate::IORING_CQE_F_BUFFER != 0
     }
 
+    /// Check if a CQE's buffer stays with the kernel for more data
+    /// (incrementally consumed buffer rings)
+    #[must_use]
+    pub fn cqe_has_buf_more(&self, cqe: &crate::io_uring_cqe) -> bool {
+        cqe.flags & crate::IORING_CQE_F_BUF_MORE != 0
+    }
+
     /// Get buffer ID from a CQE with BUFFER flag
     #[must_use]
     pub fn cqe_buffer_id(&self, cqe: &crate::io_uring_cqe) -> u16 {
@@ -145,3 +300,84 @@ impl CompletionQueue {
         cqe.flags & flags != 0
     }
 }
+
+/// Batch of CQEs taken from a [`CompletionQueue`]
+///
+/// Created by [`CompletionQueue::completions`].
+pub struct Completions<'a> {
+    cq: &'a mut CompletionQueue,
+    head: u32,
+    tail: u32,
+}
+
+impl Completions<'_> {
+    /// Copy up to `cqes.len()` remaining CQEs into `cqes`
+    ///
+    /// Returns the number of entries copied. Copied entries count as
+    /// consumed.
+    pub fn copy_into(&mut self, cqes: &mut [io_uring_cqe]) -> usize {
+        let mut copied = 0;
+        for (slot, cqe) in cqes.iter_mut().zip(&mut *self) {
+            *slot = cqe;
+            copied += 1;
+        }
+        copied
+    }
+
+    /// Consume the next CQE of a ring set up with `IORING_SETUP_CQE32`
+    ///
+    /// Returns `None` once the batch is exhausted, or if the ring posts
+    /// regular 16-byte CQEs.
+    pub fn next32(&mut self) -> Option<io_uring_cqe32> {
+        if !self.cq.is_cqe32() {
+            return None;
+        }
+
+        let cqe = self.next_ptr()?;
+        // SAFETY: in CQE32 mode every slot is 32 bytes long.
+        Some(unsafe { *cqe.cast::<io_uring_cqe32>() })
+    }
+
+    fn next_ptr(&mut self) -> Option<*const io_uring_cqe> {
+        if self.head == self.tail {
+            return None;
+        }
+
+        let cqe = self.cq.cqe_at(self.head & self.cq.kring_mask);
+        self.head = self.head.wrapping_add(1);
+        Some(cqe)
+    }
+
+    /// Number of CQEs not yet consumed from this batch
+    #[must_use]
+    pub fn remaining(&self) -> u32 {
+        self.tail.wrapping_sub(self.head)
+    }
+}
+
+impl Iterator for Completions<'_> {
+    type Item = io_uring_cqe;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cqe = self.next_ptr()?;
+        // SAFETY: the slot is masked into the CQE array and lies between
+        // head and the tail published by the kernel.
+        Some(unsafe { *cqe })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining() as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Completions<'_> {}
+
+impl Drop for Completions<'_> {
+    fn drop(&mut self) {
+        let consumed = self.head.wrapping_sub(self.cq.head.load(Ordering::Relaxed));
+        if consumed != 0 {
+            self.cq.advance(consumed);
+        }
+    }
+}
@@ -12,8 +12,35 @@ pub enum EnterError {
     SyscallFailed(rustix::io::Errno),
     BadOffset,
     UnsupportedOperation,
+    /// The kernel refused new submissions until backlogged CQEs are reaped
+    CqOverflow,
 }
 
+/// The submission queue does not have room for the entries being pushed
+pub struct PushError;
+
+/// A linked chain could not be queued or submitted
+pub enum ChainError {
+    /// The SQ lacks room for the whole chain
+    QueueFull,
+    /// The chain has no entries
+    Empty,
+    /// An entry has `IOSQE_CQE_SKIP_SUCCESS`, so its CQE may never come
+    SkipSuccess,
+    Enter(EnterError),
+}
+
+/// A multishot operation could not be re-armed
+pub enum RearmError {
+    /// The SQ has no room for the entry
+    QueueFull,
+    /// The operation ended with this error, which re-arming would only repeat
+    Failed(rustix::io::Errno),
+}
+
+/// A byte that isn't a known `IORING_OP_*` value
+pub struct UnknownOpcode(pub u8);
+
 pub type IoUringResult<T> = Result<T, InitError>;
 
 impl core::fmt::Debug for InitError {
@@ -36,6 +63,7 @@ impl core::fmt::Debug for EnterError {
             Self::SyscallFailed(e) => write!(f, "SyscallFailed({e})"),
             Self::BadOffset => write!(f, "BadOffset"),
             Self::UnsupportedOperation => write!(f, "UnsupportedOperation"),
+            Self::CqOverflow => write!(f, "CqOverflow"),
         }
     }
 }
@@ -45,3 +73,111 @@ impl From<rustix::io::Errno> for EnterError {
         Self::SyscallFailed(e)
     }
 }
+
+impl core::fmt::Debug for PushError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "PushError")
+    }
+}
+
+impl core::fmt::Debug for ChainError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::QueueFull => write!(f, "QueueFull"),
+            Self::Empty => write!(f, "Empty"),
+            Self::SkipSuccess => write!(f, "SkipSuccess"),
+            Self::Enter(e) => write!(f, "Enter({e:?})"),
+        }
+    }
+}
+
+impl From<EnterError> for ChainError {
+    fn from(e: EnterError) -> Self {
+        Self::Enter(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<InitError> for std::io::Error {
+    fn from(e: InitError) -> Self {
+        match e {
+            InitError::MmapFailed(errno)
+            | InitError::FcntlFailed(errno)
+            | InitError::SyscallFailed(errno)
+            | InitError::RegisterFailed(errno) => errno.into(),
+            InitError::UnsupportedKernel | InitError::FeatureNotSupported(_) => {
+                Self::new(std::io::ErrorKind::Unsupported, std::format!("{e:?}"))
+            }
+            InitError::InvalidParameters => {
+                Self::new(std::io::ErrorKind::InvalidInput, "InvalidParameters")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<EnterError> for std::io::Error {
+    fn from(e: EnterError) -> Self {
+        match e {
+            EnterError::SyscallFailed(errno) => errno.into(),
+            EnterError::BadOffset => Self::new(std::io::ErrorKind::InvalidInput, "BadOffset"),
+            EnterError::UnsupportedOperation => {
+                Self::new(std::io::ErrorKind::Unsupported, "UnsupportedOperation")
+            }
+            EnterError::CqOverflow => Self::new(std::io::ErrorKind::WouldBlock, "CqOverflow"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<PushError> for std::io::Error {
+    fn from(_: PushError) -> Self {
+        Self::new(std::io::ErrorKind::WouldBlock, "PushError")
+    }
+}
+
+impl core::fmt::Debug for RearmError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::QueueFull => write!(f, "QueueFull"),
+            Self::Failed(e) => write!(f, "Failed({e})"),
+        }
+    }
+}
+
+impl From<PushError> for RearmError {
+    fn from(_: PushError) -> Self {
+        Self::QueueFull
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<RearmError> for std::io::Error {
+    fn from(e: RearmError) -> Self {
+        match e {
+            RearmError::QueueFull => Self::new(std::io::ErrorKind::WouldBlock, "QueueFull"),
+            RearmError::Failed(errno) => errno.into(),
+        }
+    }
+}
+
+impl core::fmt::Debug for UnknownOpcode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "UnknownOpcode({})", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ChainError> for std::io::Error {
+    fn from(e: ChainError) -> Self {
+        match e {
+            ChainError::QueueFull => {
+                Self::new(std::io::ErrorKind::WouldBlock, std::format!("{e:?}"))
+            }
+            ChainError::Empty | ChainError::SkipSuccess => {
+                Self::new(std::io::ErrorKind::InvalidInput, std::format!("{e:?}"))
+            }
+            ChainError::Enter(e) => e.into(),
+        }
+    }
+}
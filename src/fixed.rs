@@ -0,0 +1,401 @@
+//! Registered buffers and files with tracked ownership.
+//!
+//! [`FixedBufferPool`] maps and registers a set of equally sized buffers
+//! with `IORING_REGISTER_BUFFERS`, then leases them out one at a time as
+//! [`FixedBuf`]s. A lease knows its index in the registered table, so the
+//! owned [`ReadFixed`](crate::owned::ReadFixed) and
+//! [`WriteFixed`](crate::owned::WriteFixed) operations need nothing else, and
+//! hold the lease until the kernel is done with the buffer. The memory is
+//! unregistered and unmapped once the pool and every lease are gone.
+//!
+//! [`FixedFileTable`] registers a sparse file table and keeps track of which
+//! slots are taken, handing them out as [`FixedFd`]s.
+
+use core::ffi::c_void;
+use core::ops::{Deref, DerefMut, Range};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use rustix::fd::OwnedFd;
+use rustix::io::Errno;
+use rustix::io_uring::IoringRegisterOp;
+use rustix::mm::{MapFlags, ProtFlags};
+
+use crate::err::InitError;
+use crate::io_uring::IoUring;
+use crate::slab::IndexList;
+use crate::sqe::FixedFd;
+use crate::Iovec;
+
+/// Alignment of every buffer in a [`FixedBufferPool`], enough for
+/// `O_DIRECT` on any common block device
+pub const FIXED_BUF_ALIGN: usize = 4096;
+
+struct Pool {
+    // Duplicate of the ring fd, to unregister the buffers once the last
+    // lease is gone
+    fd: Option<OwnedFd>,
+    // The ring's buffer table generation, and its value once the pool's
+    // buffers were registered. Any other value means the table was
+    // unregistered since, and possibly replaced by someone else's.
+    table: Arc<AtomicU32>,
+    generation: u32,
+    mem: NonNull<u8>,
+    mem_size: usize,
+    // Distance between the starts of consecutive buffers
+    stride: usize,
+    buf_len: usize,
+    free: IndexList,
+}
+
+impl Pool {
+    fn buf_ptr(&self, index: u16) -> *mut u8 {
+        // SAFETY: every leased index is within the mapping
+        unsafe { self.mem.as_ptr().add(usize::from(index) * self.stride) }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Claiming the table moves the generation on, like unregistering it
+        let owned = self
+            .table
+            .compare_exchange(
+                self.generation,
+                self.generation.wrapping_add(1),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok();
+        if owned {
+            let Some(fd) = &self.fd else {
+                // The kernel may still have the pages registered
+                return;
+            };
+            // SAFETY: `UnregisterBuffers` takes no argument
+            let unregistered = unsafe {
+                rustix::io_uring::io_uring_register(
+                    fd,
+                    IoringRegisterOp::UnregisterBuffers,
+                    core::ptr::null(),
+                    0,
+                )
+            };
+            if unregistered.is_err() {
+                return;
+            }
+        }
+        // SAFETY: mapped in `FixedBufferPool::new`; no lease is left, and the
+        // pages are no longer registered. The kernel keeps them pinned for
+        // requests still using them.
+        let _ = unsafe { rustix::mm::munmap(self.mem.as_ptr().cast(), self.mem_size) };
+    }
+}
+
+/// A ring's registered buffer table, leased out one buffer at a time
+///
+/// A ring has a single buffer table, so it can hold one pool at a time, and
+/// not alongside buffers registered with
+/// [`IoUring::register_buffers`](crate::IoUring::register_buffers). Like
+/// [`BufRing`](crate::BufRing), the pool keeps a duplicate of the ring fd to
+/// unregister its buffers after the `IoUring` is gone. If the table was
+/// unregistered through the ring in the meantime, the pool leaves alone
+/// whatever was registered after it.
+pub struct FixedBufferPool {
+    pool: Rc<Pool>,
+}
+
+impl FixedBufferPool {
+    /// Map `count` buffers of `buf_len` bytes and register them with `ring`
+    ///
+    /// Each buffer starts on a [`FIXED_BUF_ALIGN`] boundary.
+    ///
+    /// ## Errors
+    /// Returns `InitError::InvalidParameters` if `count` or `buf_len` is
+    /// zero, `InitError::MmapFailed` if the memory can't be mapped, or
+    /// `InitError::RegisterFailed` if the kernel rejects the buffers, e.g.
+    /// with `EBUSY` when the ring already has some registered.
+    pub fn new(ring: &IoUring, count: u16, buf_len: usize) -> Result<Self, InitError> {
+        if count == 0 || buf_len == 0 {
+            return Err(InitError::InvalidParameters);
+        }
+        let stride = buf_len
+            .checked_next_multiple_of(FIXED_BUF_ALIGN)
+            .ok_or(InitError::InvalidParameters)?;
+        let mem_size = stride
+            .checked_mul(usize::from(count))
+            .ok_or(InitError::InvalidParameters)?;
+
+        // SAFETY: a fresh private mapping doesn't alias anything
+        let addr = unsafe {
+            rustix::mm::mmap_anonymous(
+                core::ptr::null_mut(),
+                mem_size,
+                ProtFlags::READ | ProtFlags::WRITE,
+                MapFlags::PRIVATE,
+            )
+        }
+        .map_err(InitError::MmapFailed)?;
+        let mem = NonNull::new(addr.cast::<u8>()).ok_or(InitError::MmapFailed(Errno::INVAL))?;
+
+        let iovecs: Vec<Iovec> = (0..usize::from(count))
+            .map(|i| {
+                // SAFETY: within the mapping
+                let base = unsafe { mem.as_ptr().add(i * stride) };
+                Iovec::new(base.cast::<c_void>(), buf_len)
+            })
+            .collect();
+        if let Err(e) = ring.register_buffers(&iovecs) {
+            // SAFETY: the kernel never saw the mapping
+            let _ = unsafe { rustix::mm::munmap(addr, mem_size) };
+            return Err(e);
+        }
+
+        let free = IndexList::new(u32::from(count));
+        // Push in reverse so the lowest indices are leased first
+        for index in (0..u32::from(count)).rev() {
+            free.push(index);
+        }
+        let table = ring.buffer_table_shared();
+        Ok(Self {
+            pool: Rc::new(Pool {
+                fd: ring
+                    .ring_fd()
+                    .and_then(|fd| rustix::io::fcntl_dupfd_cloexec(fd, 0).ok()),
+                generation: table.load(Ordering::Acquire),
+                table,
+                mem,
+                mem_size,
+                stride,
+                buf_len,
+                free,
+            }),
+        })
+    }
+
+    /// Lease the lowest-numbered free buffer, or `None` if all are leased
+    ///
+    /// The buffer keeps whatever its previous lease left in it.
+    #[must_use]
+    pub fn lease(&self) -> Option<FixedBuf> {
+        let index = self.pool.free.pop()?;
+        Some(FixedBuf {
+            pool: Rc::clone(&self.pool),
+            index: index as u16,
+        })
+    }
+
+    /// Number of buffers in the pool
+    #[must_use]
+    pub fn count(&self) -> u16 {
+        self.pool.free.capacity() as u16
+    }
+
+    /// Number of buffers not currently leased
+    #[must_use]
+    pub fn available(&self) -> u16 {
+        self.pool.free.len() as u16
+    }
+
+    /// Size of each buffer in bytes
+    #[must_use]
+    pub fn buf_len(&self) -> usize {
+        self.pool.buf_len
+    }
+}
+
+impl core::fmt::Debug for FixedBufferPool {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FixedBufferPool")
+            .field("count", &self.count())
+            .field("available", &self.available())
+            .field("buf_len", &self.buf_len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A leased buffer from a [`FixedBufferPool`]
+///
+/// Derefs to the whole buffer. Dropping it returns the buffer to the pool;
+/// the owned [`ReadFixed`](crate::owned::ReadFixed) and
+/// [`WriteFixed`](crate::owned::WriteFixed) operations hold it until their
+/// CQE, so it can't go back while the kernel uses it.
+pub struct FixedBuf {
+    pool: Rc<Pool>,
+    index: u16,
+}
+
+impl FixedBuf {
+    /// Index in the ring's registered buffer table, the SQE's `buf_index`
+    #[must_use]
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+}
+
+impl Deref for FixedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the lease gives this handle sole use of the buffer
+        unsafe { core::slice::from_raw_parts(self.pool.buf_ptr(self.index), self.pool.buf_len) }
+    }
+}
+
+impl DerefMut for FixedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as for `deref`
+        unsafe { core::slice::from_raw_parts_mut(self.pool.buf_ptr(self.index), self.pool.buf_len) }
+    }
+}
+
+impl Drop for FixedBuf {
+    fn drop(&mut self) {
+        self.pool.free.push(u32::from(self.index));
+    }
+}
+
+impl core::fmt::Debug for FixedBuf {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FixedBuf")
+            .field("index", &self.index)
+            .field("len", &self.pool.buf_len)
+            .finish()
+    }
+}
+
+/// A sparse registered file table, split between slots it hands out and
+/// slots the kernel allocates
+///
+/// The first `managed` slots are given out by [`insert`](Self::insert) and
+/// [`reserve`](Self::reserve). The `kernel` slots after them form the
+/// allocation range for direct opens, accepts and sockets passed
+/// [`IORING_FILE_INDEX_ALLOC`](crate::IORING_FILE_INDEX_ALLOC), whose CQE
+/// [`allocated`](Self::allocated) turns into a [`FixedFd`]. Keeping the two
+/// apart means the kernel never fills a slot the table has handed out.
+///
+/// The table is the ring's only one; it stays registered until
+/// [`IoUring::unregister_files`](crate::IoUring::unregister_files) or the
+/// ring is dropped.
+pub struct FixedFileTable {
+    free: IndexList,
+    kernel: Range<u32>,
+}
+
+impl FixedFileTable {
+    /// Register `managed + kernel` empty slots with `ring`
+    ///
+    /// ## Errors
+    /// Returns `InitError::InvalidParameters` if the sizes overflow, or
+    /// `InitError::RegisterFailed` if the kernel rejects the table or the
+    /// allocation range (Linux 6.0+).
+    pub fn new(ring: &IoUring, managed: u32, kernel: u32) -> Result<Self, InitError> {
+        let size = managed
+            .checked_add(kernel)
+            .ok_or(InitError::InvalidParameters)?;
+        ring.register_files_sparse(size)?;
+        if let Err(e) = ring.register_file_alloc_range(managed, kernel) {
+            let _ = ring.unregister_files();
+            return Err(e);
+        }
+
+        let free = IndexList::new(managed);
+        // Push in reverse so the lowest slots are handed out first
+        for index in (0..managed).rev() {
+            free.push(index);
+        }
+        Ok(Self {
+            free,
+            kernel: managed..size,
+        })
+    }
+
+    /// Total number of slots
+    #[must_use]
+    pub fn size(&self) -> u32 {
+        self.kernel.end
+    }
+
+    /// Number of managed slots not handed out
+    #[must_use]
+    pub fn available(&self) -> u32 {
+        self.free.len()
+    }
+
+    /// Slots the kernel picks from for `IORING_FILE_INDEX_ALLOC`
+    #[must_use]
+    pub fn kernel_range(&self) -> Range<u32> {
+        self.kernel.clone()
+    }
+
+    /// Take a managed slot, to be filled by a direct open, accept or socket
+    /// passed [`FixedFd::file_index`]
+    #[must_use]
+    pub fn reserve(&self) -> Option<FixedFd> {
+        self.free.pop().and_then(FixedFd::new)
+    }
+
+    /// Install `fd` in a free managed slot
+    ///
+    /// The table holds its own reference to the file, so `fd` may be closed
+    /// afterwards.
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed(Errno::NFILE)` if every managed
+    /// slot is taken, or `InitError::RegisterFailed` if the update fails.
+    pub fn insert(&self, ring: &IoUring, fd: i32) -> Result<FixedFd, InitError> {
+        let file = self
+            .reserve()
+            .ok_or(InitError::RegisterFailed(Errno::NFILE))?;
+        if let Err(e) = ring.register_files_update(file.index(), &[fd]) {
+            self.release(file);
+            return Err(e);
+        }
+        Ok(file)
+    }
+
+    /// Close the file in `file`'s slot and, if managed, free the slot
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed` if the update fails.
+    pub fn remove(&self, ring: &IoUring, file: FixedFd) -> Result<(), InitError> {
+        ring.register_files_update(file.index(), &[-1])?;
+        self.release(file);
+        Ok(())
+    }
+
+    /// Free a managed slot whose file is already closed, e.g. by
+    /// [`CloseDirect`](crate::sqe::CloseDirect)
+    ///
+    /// Returns `false`, changing nothing, for slots in the kernel range and
+    /// slots that are free already.
+    pub fn release(&self, file: FixedFd) -> bool {
+        self.free.push(file.index())
+    }
+
+    /// The slot the kernel picked for an `IORING_FILE_INDEX_ALLOC` operation,
+    /// from its CQE's `res`
+    ///
+    /// Returns `None` for errors, such as `-ENFILE` when the kernel range is
+    /// full, and for results outside the kernel range.
+    #[must_use]
+    pub fn allocated(&self, res: i32) -> Option<FixedFd> {
+        u32::try_from(res)
+            .ok()
+            .filter(|index| self.kernel.contains(index))
+            .and_then(FixedFd::new)
+    }
+}
+
+impl core::fmt::Debug for FixedFileTable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FixedFileTable")
+            .field("size", &self.size())
+            .field("available", &self.available())
+            .field("kernel_range", &self.kernel)
+            .finish_non_exhaustive()
+    }
+}
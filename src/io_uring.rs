@@ -1,40 +1,71 @@
 use core::ffi::{c_void, CStr};
 use core::ptr::{null, null_mut};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 
 use rustix::fd::{AsFd, AsRawFd, OwnedFd};
 use rustix::io::{self, Errno};
 use rustix::io_uring::{self, io_uring_files_update, sigset_t, IoringEnterFlags, IoringRegisterOp};
 
-use crate::cq::CompletionQueue;
-use crate::err::{EnterError, InitError};
-use crate::mmap::RwMmap;
+use crate::caps::Capabilities;
+use crate::cq::{CompletionQueue, Completions, OverflowStatus};
+
+use crate::err::{EnterError, InitError, PushError, RearmError};
+use crate::mmap::{RingMemory, RwMmap, UserRingMemory};
+use crate::opcode::Opcode;
 use crate::sq::SubmissionQueue;
+use crate::submit::{RingIndex, Submitter};
 use crate::{
-    io_uring_sqe, Iovec, PrepSqe, PrepSqeMut, IORING_OFF_CQ_RING, IORING_OFF_SQES,
-    IORING_OFF_SQ_RING, IORING_OP_NOP, IORING_SETUP_ATTACH_WQ, IORING_SETUP_CLAMP,
-    IORING_SETUP_COOP_TASKRUN, IORING_SETUP_CQE32, IORING_SETUP_CQSIZE, IORING_SETUP_R_DISABLED,
-    IORING_SETUP_SQE128, IORING_SETUP_SQPOLL, IORING_SETUP_SQ_AFF, IORING_SETUP_SUBMIT_ALL,
-    IORING_SETUP_TASKRUN_FLAG,
+    io_uring_sqe, Iovec, PrepSqe, PrepSqeMut, IORING_OP_NOP, IORING_SETUP_ATTACH_WQ,
+    IORING_SETUP_CLAMP, IORING_SETUP_COOP_TASKRUN, IORING_SETUP_CQE32, IORING_SETUP_CQSIZE,
+    IORING_SETUP_DEFER_TASKRUN, IORING_SETUP_NO_MMAP, IORING_SETUP_R_DISABLED,
+    IORING_SETUP_SINGLE_ISSUER, IORING_SETUP_SQE128, IORING_SETUP_SQPOLL, IORING_SETUP_SQ_AFF,
+    IORING_SETUP_SUBMIT_ALL, IORING_SETUP_TASKRUN_FLAG,
 };
 
 pub struct IoUring {
-    fd: OwnedFd,
-    _sq_mmap: RwMmap,
-    _cq_mmap: RwMmap,
-    _sqe_mmap: RwMmap,
+    // `None` once closed with `close_ring_fd`
+    fd: Option<OwnedFd>,
+    // Index of the ring fd in the registered ring table, if registered
+    ring_index: Option<RingIndex>,
+    memory: RingMemory,
+    // In-flight counter shared by the queues, with `cap_inflight`. It gets
+    // a mapping of its own, as the queues may be split across threads and
+    // the ring moved.
+    inflight: Option<RwMmap>,
     sq: SubmissionQueue,
     cq: CompletionQueue,
     // User data allocator for automatic user_data management
-    next_user_data: core::sync::atomic::AtomicU64,
-    free_user_data: core::cell::RefCell<Vec<u64>>,
+    #[cfg(feature = "alloc")]
+    user_data: crate::slab::UserDataSlab,
+    // Chains queued so far, numbering each chain's `user_data`
+    #[cfg(feature = "alloc")]
+    chains: u32,
+    // Generation of the registered buffer table, shared with the
+    // `FixedBufferPool`s that may outlive the ring
+    #[cfg(feature = "alloc")]
+    buffer_table: alloc::sync::Arc<AtomicU32>,
+    #[cfg(not(feature = "alloc"))]
+    buffer_table: AtomicU32,
     // Setup parameters for feature detection
     params: io_uring::io_uring_params,
+    kernel_version: (u32, u32, u32),
+    // Opcode probe, queried on first use
+    probe: core::cell::OnceCell<Probe>,
 }
 
-const PROBE_OPS: usize = 128;
+pub(crate) const PROBE_OPS: usize = 128;
+
+// Numbers rings so `OwnedOps` can tell which one a queue belongs to. Unlike
+// the addresses of the mappings, numbers are never reused.
+static NEXT_RING_ID: AtomicUsize = AtomicUsize::new(1);
 
 /// Configuration for advanced io_uring setup
-#[derive(Debug, Clone, Default)]
+///
+/// Cloning a builder leaves out the memory given to
+/// [`no_mmap`](Self::no_mmap), which can back a single ring only. Such a
+/// clone still asks for `IORING_SETUP_NO_MMAP`, and fails to build with
+/// `InitError::InvalidParameters` until given memory of its own.
+#[derive(Debug, Default)]
 pub struct SetupBuilder {
     sq_entries: Option<u32>,
     cq_entries: Option<u32>,
@@ -43,6 +74,24 @@ pub struct SetupBuilder {
     sq_thread_idle: Option<u32>,
     attach_wq_fd: Option<i32>,
     wq_names: Option<&'static [u8]>,
+    cap_inflight: bool,
+    memory: Option<UserRingMemory>,
+}
+
+impl Clone for SetupBuilder {
+    fn clone(&self) -> Self {
+        Self {
+            sq_entries: self.sq_entries,
+            cq_entries: self.cq_entries,
+            flags: self.flags,
+            sq_thread_cpu: self.sq_thread_cpu,
+            sq_thread_idle: self.sq_thread_idle,
+            attach_wq_fd: self.attach_wq_fd,
+            wq_names: self.wq_names,
+            cap_inflight: self.cap_inflight,
+            memory: None,
+        }
+    }
 }
 
 impl SetupBuilder {
@@ -77,6 +126,9 @@ impl SetupBuilder {
     }
 
     /// Enable IORING_SETUP_SQPOLL flag for kernel-side SQ polling thread
+    ///
+    /// Submitting then only enters the kernel when the thread has gone idle
+    /// and needs a wakeup.
     #[must_use]
     pub fn sqpoll(mut self) -> Self {
         self.flags |= IORING_SETUP_SQPOLL;
@@ -159,43 +211,213 @@ impl SetupBuilder {
         self
     }
 
+    /// Enable IORING_SETUP_SINGLE_ISSUER flag
+    ///
+    /// Promises the kernel that only one thread submits to the ring, which
+    /// lets it skip some synchronization. Submitting from another thread
+    /// fails with `EEXIST`.
+    #[must_use]
+    pub fn single_issuer(mut self) -> Self {
+        self.flags |= IORING_SETUP_SINGLE_ISSUER;
+        self
+    }
+
+    /// Enable IORING_SETUP_DEFER_TASKRUN flag
+    ///
+    /// Completion work is deferred until the submitting thread enters the
+    /// ring with `IORING_ENTER_GETEVENTS`, which `submit` and `peek_cqe` do
+    /// as needed. Requires [`single_issuer`](Self::single_issuer).
+    #[must_use]
+    pub fn defer_taskrun(mut self) -> Self {
+        self.flags |= IORING_SETUP_DEFER_TASKRUN;
+        self
+    }
+
+    /// Cap in-flight submissions at the CQ size
+    ///
+    /// Pushing and getting SQEs fails once as many entries are queued or
+    /// awaiting completion as the CQ can hold, so completions can't overflow.
+    /// An entry counts until its last CQE, the one without
+    /// `IORING_CQE_F_MORE`, is reaped through the [`CompletionQueue`].
+    /// Multishot requests may still post more CQEs than the CQ holds.
+    ///
+    /// A CQE doesn't tell which request it belongs to; only resource tag
+    /// CQEs are set apart, by their [`RsrcTag`](crate::RsrcTag) `user_data`.
+    /// So entries with `IOSQE_CQE_SKIP_SUCCESS`, which post a CQE only if
+    /// they fail, are refused by `push`, and must not be set through raw
+    /// SQEs either. Nor may the ring be the target of `IORING_OP_MSG_RING`,
+    /// as CQEs posted that way would be taken for completions.
+    #[must_use]
+    pub fn cap_inflight(mut self) -> Self {
+        self.cap_inflight = true;
+        self
+    }
+
+    /// Enable IORING_SETUP_NO_MMAP flag, placing the rings in `memory`
+    ///
+    /// Building fails with `InitError::InvalidParameters` if either region
+    /// is smaller than [`no_mmap_sizes`](Self::no_mmap_sizes) requires.
+    #[must_use]
+    pub fn no_mmap(mut self, memory: UserRingMemory) -> Self {
+        self.memory = Some(memory);
+        self.flags |= IORING_SETUP_NO_MMAP;
+        self
+    }
+
+    /// Sizes `(rings, sqes)` in bytes that memory passed to
+    /// [`no_mmap`](Self::no_mmap) needs for the configured entry counts
+    ///
+    /// The rings size is an upper bound, since the exact layout of the
+    /// kernel's ring header depends on its cache line size.
+    #[must_use]
+    pub fn no_mmap_sizes(&self) -> (usize, usize) {
+        // Three cache lines of head, tail and flags, for cache lines of up
+        // to 256 bytes
+        const RINGS_HEADER_MAX: usize = 3 * 256;
+
+        let (sq_entries, cq_entries) = self.entries();
+        let sq_entries = sq_entries.next_power_of_two() as usize;
+        let cq_entries = if self.flags & IORING_SETUP_CQSIZE != 0 {
+            cq_entries.next_power_of_two() as usize
+        } else {
+            2 * sq_entries
+        };
+
+        let (sqe_size, cqe_size) = (
+            if self.flags & IORING_SETUP_SQE128 != 0 {
+                core::mem::size_of::<crate::io_uring_sqe128>()
+            } else {
+                core::mem::size_of::<io_uring_sqe>()
+            },
+            if self.flags & IORING_SETUP_CQE32 != 0 {
+                core::mem::size_of::<crate::io_uring_cqe32>()
+            } else {
+                core::mem::size_of::<crate::io_uring_cqe>()
+            },
+        );
+
+        let cqes_end = (RINGS_HEADER_MAX + cq_entries * cqe_size).next_multiple_of(256);
+        let rings = cqes_end + sq_entries * core::mem::size_of::<u32>();
+        (rings, sq_entries * sqe_size)
+    }
+
+    /// Requested `(sq_entries, cq_entries)`
+    fn entries(&self) -> (u32, u32) {
+        let sq_entries = self
+            .sq_entries
+            .unwrap_or_else(|| {
+                if self.flags & IORING_SETUP_CQSIZE != 0 {
+                    // If custom CQ size is set, use minimum SQ size
+                    1
+                } else {
+                    32
+                }
+            })
+            .clamp(1, 4096);
+
+        // Only use cq_entries if CQSIZE flag is set
+        let cq_entries = if self.flags & IORING_SETUP_CQSIZE != 0 {
+            self.cq_entries.unwrap_or(32).clamp(1, 4096)
+        } else {
+            sq_entries
+        };
+
+        (sq_entries, cq_entries)
+    }
+
     /// Build the io_uring instance with the configured options
     pub fn build(self) -> Result<IoUring, InitError> {
         IoUring::with_setup(self)
     }
 }
 
+// `struct io_uring_probe` header, with the opcode as a plain byte: newer
+// kernels report opcodes rustix's `IoringOp` has no variant for
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default)]
+struct ProbeHeader {
+    last_op: u8,
+    ops_len: u8,
+    resv: u16,
+    resv2: [u32; 3],
+}
+
+// `struct io_uring_probe_op`, likewise
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct ProbeOp {
+    op: u8,
+    resv: u8,
+    flags: u16,
+    resv2: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
 pub struct Probe {
-    probe: io_uring::io_uring_probe,
-    ops: [io_uring::io_uring_probe_op; PROBE_OPS],
+    header: ProbeHeader,
+    ops: [ProbeOp; PROBE_OPS],
 }
 
 impl Probe {
     #[must_use]
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
-            probe: io_uring::io_uring_probe::default(),
-            ops: [io_uring::io_uring_probe_op::default(); PROBE_OPS],
+            header: ProbeHeader::default(),
+            ops: [ProbeOp::default(); PROBE_OPS],
         }
     }
 
     #[must_use]
     fn ops_len(&self) -> usize {
-        (self.probe.ops_len as usize).min(self.ops.len())
+        usize::from(self.header.ops_len).min(self.ops.len())
     }
 
     #[must_use]
-    fn ops_slice(&self) -> &[io_uring::io_uring_probe_op] {
+    fn ops_slice(&self) -> &[ProbeOp] {
         &self.ops[..self.ops_len()]
     }
 
+    /// Highest opcode the kernel knows
+    #[must_use]
+    pub fn last_op(&self) -> u8 {
+        self.header.last_op
+    }
+
+    /// `IO_URING_OP_*` flags the kernel reported for `opcode`
+    ///
+    /// Returns empty flags for opcodes past [`last_op`](Self::last_op).
+    #[must_use]
+    pub fn op_flags(&self, opcode: u8) -> io_uring::IoringOpFlags {
+        self.ops_slice()
+            .iter()
+            .find(|op| op.op == opcode)
+            .map_or(io_uring::IoringOpFlags::empty(), |op| {
+                io_uring::IoringOpFlags::from_bits_retain(op.flags)
+            })
+    }
+
     #[must_use]
     pub fn opcode_supported(&self, opcode: u8) -> bool {
-        self.ops_slice().iter().any(|op| {
-            op.op as u8 == opcode && op.flags.contains(io_uring::IoringOpFlags::SUPPORTED)
-        })
+        self.op_flags(opcode)
+            .contains(io_uring::IoringOpFlags::SUPPORTED)
+    }
+
+    /// Whether the kernel supports `opcode`
+    #[must_use]
+    pub fn is_supported(&self, opcode: Opcode) -> bool {
+        self.opcode_supported(opcode.into())
+    }
+
+    /// Iterate over the supported opcodes this crate knows
+    pub fn supported_ops(&self) -> impl Iterator<Item = Opcode> + '_ {
+        self.ops_slice()
+            .iter()
+            .filter(|op| {
+                io_uring::IoringOpFlags::from_bits_retain(op.flags)
+                    .contains(io_uring::IoringOpFlags::SUPPORTED)
+            })
+            .filter_map(|op| Opcode::try_from(op.op).ok())
     }
 }
 
@@ -215,26 +437,30 @@ impl IoUring {
     fn with_setup(config: SetupBuilder) -> Result<Self, InitError> {
         let mut params = io_uring::io_uring_params::default();
 
-        let sq_entries = config
-            .sq_entries
-            .unwrap_or_else(|| {
-                if config.flags & IORING_SETUP_CQSIZE != 0 {
-                    // If custom CQ size is set, use minimum SQ size
-                    1
-                } else {
-                    32
-                }
-            })
-            .clamp(1, 4096);
-
+        let (sq_entries, cq_entries) = config.entries();
         params.sq_entries = sq_entries;
+        params.cq_entries = cq_entries;
 
-        // Only set cq_entries if CQSIZE flag is set
-        if config.flags & IORING_SETUP_CQSIZE != 0 {
-            let cq_entries = config.cq_entries.unwrap_or(32).clamp(1, 4096);
-            params.cq_entries = cq_entries;
-        } else {
-            params.cq_entries = sq_entries;
+        if config.flags & IORING_SETUP_DEFER_TASKRUN != 0
+            && config.flags & IORING_SETUP_SINGLE_ISSUER == 0
+        {
+            return Err(InitError::InvalidParameters);
+        }
+
+        // A clone of a NO_MMAP builder, which lost its memory
+        if config.flags & IORING_SETUP_NO_MMAP != 0 && config.memory.is_none() {
+            return Err(InitError::InvalidParameters);
+        }
+
+        if let Some(memory) = &config.memory {
+            let (rings_size, sqes_size) = config.no_mmap_sizes();
+            if memory.rings_size() < rings_size || memory.sqes_size() < sqes_size {
+                return Err(InitError::InvalidParameters);
+            }
+            // With NO_MMAP, `user_addr` in the offsets tells the kernel where
+            // the rings and SQEs live.
+            params.cq_off.resv2 = memory.rings_addr();
+            params.sq_off.resv2 = memory.sqes_addr();
         }
 
         params.flags = rustix::io_uring::IoringSetupFlags::from_bits_retain(config.flags);
@@ -253,174 +479,387 @@ impl IoUring {
         let fd = rustix::io_uring::io_uring_setup(sq_entries, &mut params)
             .map_err(InitError::SyscallFailed)?;
 
-        let ring = Self::create_ring(fd, params)?;
+        let memory = match config.memory {
+            Some(memory) => RingMemory::user(memory),
+            None => RingMemory::map(fd.as_raw_fd(), &params)?,
+        };
+        let mut ring = Self::create_ring(fd, params, memory);
+
+        if config.cap_inflight {
+            let counter = RwMmap::anonymous(core::mem::size_of::<AtomicU32>())?;
+            let outstanding = counter.as_ptr().cast::<AtomicU32>().cast_const();
+            ring.sq
+                .set_inflight_limit(outstanding, ring.params.cq_entries);
+            ring.cq.set_outstanding(outstanding);
+            ring.inflight = Some(counter);
+        }
 
         Ok(ring)
     }
 
-    fn create_ring(fd: OwnedFd, params: io_uring::io_uring_params) -> Result<Self, InitError> {
-        let sq_ring_size = params.sq_off.array as usize
-            + (params.sq_entries as usize * core::mem::size_of::<u32>());
-        let cq_ring_size = params.cq_off.cqes as usize
-            + (params.cq_entries as usize * core::mem::size_of::<crate::io_uring_cqe>());
-
-        // Handle extended SQE format (128 bytes instead of 64)
-        let sqe_size =
-            if params
-                .flags
-                .contains(rustix::io_uring::IoringSetupFlags::from_bits_retain(
-                    IORING_SETUP_SQE128,
-                ))
-            {
-                (params.sq_entries as usize) * 128
-            } else {
-                (params.sq_entries as usize) * core::mem::size_of::<io_uring_sqe>()
-            };
-
-        let sq_mmap = RwMmap::new(fd.as_raw_fd(), IORING_OFF_SQ_RING, sq_ring_size, true)?;
-        let cq_mmap = RwMmap::new(fd.as_raw_fd(), IORING_OFF_CQ_RING, cq_ring_size, true)?;
-        let sqe_mmap_mapping = RwMmap::new(fd.as_raw_fd(), IORING_OFF_SQES, sqe_size, true)?;
+    fn create_ring(fd: OwnedFd, params: io_uring::io_uring_params, memory: RingMemory) -> Self {
+        let sqe128 = params.flags.bits() & IORING_SETUP_SQE128 != 0;
+        let cqe32 = params.flags.bits() & IORING_SETUP_CQE32 != 0;
 
-        let sq = unsafe {
+        let mut sq = unsafe {
             SubmissionQueue::new(
-                sq_mmap.as_ptr() as *mut u8,
+                memory.sq_ring_ptr(),
                 &params.sq_off,
-                sqe_mmap_mapping.as_ptr() as *mut io_uring_sqe,
+                memory.sqes_ptr(),
                 params.sq_entries,
+                sqe128,
             )
         };
-        let cq = unsafe { CompletionQueue::new(cq_mmap.as_ptr() as *mut u8, &params.cq_off) };
+        let mut cq = unsafe { CompletionQueue::new(memory.cq_ring_ptr(), &params.cq_off, cqe32) };
 
-        Ok(Self {
-            fd,
-            _sq_mmap: sq_mmap,
-            _cq_mmap: cq_mmap,
-            _sqe_mmap: sqe_mmap_mapping,
+        let ring_id = NEXT_RING_ID.fetch_add(1, Ordering::Relaxed);
+        sq.set_ring_id(ring_id);
+        cq.set_ring_id(ring_id);
+
+        Self {
+            fd: Some(fd),
+            ring_index: None,
+            memory,
+            inflight: None,
             sq,
             cq,
-            next_user_data: core::sync::atomic::AtomicU64::new(1),
-            free_user_data: core::cell::RefCell::new(Vec::new()),
+            #[cfg(feature = "alloc")]
+            user_data: crate::slab::UserDataSlab::new(params.cq_entries),
+            #[cfg(feature = "alloc")]
+            chains: 0,
+            buffer_table: AtomicU32::new(0).into(),
             params,
-        })
+            kernel_version: crate::caps::kernel_version(),
+            probe: core::cell::OnceCell::new(),
+        }
     }
 
-    fn register(
-        &self,
-        opcode: IoringRegisterOp,
-        arg: *const c_void,
-        nr_args: u32,
-    ) -> Result<u32, InitError> {
-        // SAFETY: `io_uring_register` doesn't retain `arg`; it only reads it
-        // for the duration of the syscall.
-        unsafe { io_uring::io_uring_register(self.fd.as_fd(), opcode, arg, nr_args) }
-            .map_err(InitError::RegisterFailed)
+    /// Get a [`Submitter`] for this ring
+    ///
+    /// The submitter can enter the ring and register resources through a
+    /// shared reference.
+    #[must_use]
+    pub fn submitter(&self) -> Submitter<'_> {
+        Submitter::new(
+            self.fd.as_ref(),
+            self.ring_index,
+            false,
+            self.buffer_table(),
+            &self.params,
+            &self.sq,
+        )
     }
 
-    pub fn register_buffers(&self, iovecs: &[Iovec]) -> Result<(), InitError> {
-        if iovecs.is_empty() {
-            return Err(InitError::InvalidParameters);
-        }
+    /// A submitter for the ring's own use, which always takes the registered
+    /// ring fd if there is one, as the ring never leaves the registering
+    /// thread
+    fn ring_submitter(&self) -> Submitter<'_> {
+        Submitter::new(
+            self.fd.as_ref(),
+            self.ring_index,
+            true,
+            self.buffer_table(),
+            &self.params,
+            &self.sq,
+        )
+    }
+
+    /// Generation of the registered buffer table, moving on with every
+    /// registration and unregistration
+    fn buffer_table(&self) -> &AtomicU32 {
+        &self.buffer_table
+    }
 
-        let nr_args: u32 = iovecs
-            .len()
-            .try_into()
-            .map_err(|_| InitError::InvalidParameters)?;
+    /// The shared generation of the buffer table, for a `FixedBufferPool`
+    /// registering it
+    #[cfg(feature = "alloc")]
+    pub(crate) fn buffer_table_shared(&self) -> alloc::sync::Arc<AtomicU32> {
+        alloc::sync::Arc::clone(&self.buffer_table)
+    }
 
-        self.register(
-            IoringRegisterOp::RegisterBuffers,
-            iovecs.as_ptr() as *const c_void,
-            nr_args,
-        )?;
+    /// Split the ring into its submitter, submission queue and completion
+    /// queue
+    ///
+    /// The two queues are `Send` and can be moved to separate threads, while
+    /// the `Submitter` is `Sync` and can be shared between them. The SQ
+    /// producer publishes its entries with [`SubmissionQueue::sync`] before
+    /// calling [`Submitter::submit`].
+    pub fn split(&mut self) -> (Submitter<'_>, &mut SubmissionQueue, &mut CompletionQueue) {
+        let submitter = Submitter::new(
+            self.fd.as_ref(),
+            self.ring_index,
+            false,
+            &self.buffer_table,
+            &self.params,
+            &self.sq,
+        );
+        (submitter, &mut self.sq, &mut self.cq)
+    }
 
-        Ok(())
+    /// The submission queue of this ring
+    pub fn submission(&mut self) -> &mut SubmissionQueue {
+        &mut self.sq
     }
 
-    pub fn unregister_buffers(&self) -> Result<(), InitError> {
-        self.register(IoringRegisterOp::UnregisterBuffers, null(), 0)?;
-        Ok(())
+    /// The completion queue of this ring
+    pub fn completion(&mut self) -> &mut CompletionQueue {
+        &mut self.cq
     }
 
-    pub fn register_files(&self, fds: &[i32]) -> Result<(), InitError> {
-        if fds.is_empty() {
-            return Err(InitError::InvalidParameters);
-        }
+    /// The memory backing this ring's queues
+    #[must_use]
+    pub fn ring_memory(&self) -> &RingMemory {
+        &self.memory
+    }
 
-        let nr_args: u32 = fds
-            .len()
-            .try_into()
-            .map_err(|_| InitError::InvalidParameters)?;
+    pub fn register_buffers(&self, iovecs: &[Iovec]) -> Result<(), InitError> {
+        self.ring_submitter().register_buffers(iovecs)
+    }
 
-        self.register(
-            IoringRegisterOp::RegisterFiles,
-            fds.as_ptr() as *const c_void,
-            nr_args,
-        )?;
+    pub fn unregister_buffers(&self) -> Result<(), InitError> {
+        self.ring_submitter().unregister_buffers()
+    }
 
-        Ok(())
+    pub fn register_files(&self, fds: &[i32]) -> Result<(), InitError> {
+        self.ring_submitter().register_files(fds)
     }
 
     pub fn unregister_files(&self) -> Result<(), InitError> {
-        self.register(IoringRegisterOp::UnregisterFiles, null(), 0)?;
-        Ok(())
+        self.ring_submitter().unregister_files()
     }
 
     pub fn register_files_update(&self, offset: u32, fds: &[i32]) -> Result<(), InitError> {
-        if fds.is_empty() {
-            return Err(InitError::InvalidParameters);
-        }
+        self.ring_submitter().register_files_update(offset, fds)
+    }
 
-        let nr_args: u32 = fds
-            .len()
-            .try_into()
-            .map_err(|_| InitError::InvalidParameters)?;
-        let update = io_uring_files_update {
-            offset,
-            resv: 0,
-            fds: fds.as_ptr() as u64,
-        };
+    pub fn register_pbuf_ring(&self, reg: &crate::PbufRingReg) -> Result<(), InitError> {
+        self.ring_submitter().register_pbuf_ring(reg)
+    }
 
-        self.register(
-            IoringRegisterOp::RegisterFilesUpdate,
-            (&update as *const io_uring_files_update).cast::<c_void>(),
-            nr_args,
-        )?;
+    pub fn unregister_pbuf_ring(&self, bgid: u16) -> Result<(), InitError> {
+        self.ring_submitter().unregister_pbuf_ring(bgid)
+    }
 
-        Ok(())
+    pub fn register_files_sparse(&self, nr: u32) -> Result<(), InitError> {
+        self.ring_submitter().register_files_sparse(nr)
+    }
+
+    pub fn register_file_alloc_range(&self, offset: u32, len: u32) -> Result<(), InitError> {
+        self.ring_submitter().register_file_alloc_range(offset, len)
+    }
+
+    pub fn register_buffers_tagged(
+        &self,
+        iovecs: &[Iovec],
+        tags: &[crate::RsrcTag],
+    ) -> Result<(), InitError> {
+        self.ring_submitter().register_buffers_tagged(iovecs, tags)
+    }
+
+    pub fn register_files_tagged(
+        &self,
+        fds: &[i32],
+        tags: &[crate::RsrcTag],
+    ) -> Result<(), InitError> {
+        self.ring_submitter().register_files_tagged(fds, tags)
+    }
+
+    pub fn register_buffers_update_tagged(
+        &self,
+        offset: u32,
+        iovecs: &[Iovec],
+        tags: &[crate::RsrcTag],
+    ) -> Result<(), InitError> {
+        self.ring_submitter()
+            .register_buffers_update_tagged(offset, iovecs, tags)
+    }
+
+    pub fn register_files_update_tagged(
+        &self,
+        offset: u32,
+        fds: &[i32],
+        tags: &[crate::RsrcTag],
+    ) -> Result<(), InitError> {
+        self.ring_submitter()
+            .register_files_update_tagged(offset, fds, tags)
+    }
+
+    pub fn register_restrictions(
+        &self,
+        restrictions: &[crate::Restriction],
+    ) -> Result<(), InitError> {
+        self.ring_submitter().register_restrictions(restrictions)
+    }
+
+    pub fn enable_rings(&self) -> Result<(), InitError> {
+        self.ring_submitter().enable_rings()
+    }
+
+    /// Cap the number of io-wq workers, per NUMA node, returning the
+    /// previous limits
+    ///
+    /// A zero limit is left unchanged; `set_iowq_max_workers(0, 0)` only
+    /// reads the current ones.
+    ///
+    /// ## Errors
+    /// As for [`Submitter::register_iowq_max_workers`](crate::Submitter::register_iowq_max_workers).
+    pub fn set_iowq_max_workers(
+        &self,
+        bounded: u32,
+        unbounded: u32,
+    ) -> Result<crate::IowqMaxWorkers, InitError> {
+        let mut workers = crate::IowqMaxWorkers { bounded, unbounded };
+        self.ring_submitter()
+            .register_iowq_max_workers(&mut workers)?;
+        Ok(workers)
+    }
+
+    /// Pin the ring's io-wq workers to `cpus`
+    ///
+    /// ## Errors
+    /// As for [`Submitter::register_iowq_aff`](crate::Submitter::register_iowq_aff).
+    pub fn set_iowq_affinity(&self, cpus: &crate::CpuSet) -> Result<(), InitError> {
+        self.ring_submitter().register_iowq_aff(cpus)
+    }
+
+    /// Undo [`set_iowq_affinity`](Self::set_iowq_affinity)
+    ///
+    /// ## Errors
+    /// As for [`Submitter::unregister_iowq_aff`](crate::Submitter::unregister_iowq_aff).
+    pub fn clear_iowq_affinity(&self) -> Result<(), InitError> {
+        self.ring_submitter().unregister_iowq_aff()
+    }
+
+    pub fn register_personality(&self) -> Result<u16, InitError> {
+        self.ring_submitter().register_personality()
+    }
+
+    pub fn unregister_personality(&self, id: u16) -> Result<(), InitError> {
+        self.ring_submitter().unregister_personality(id)
     }
 
     pub fn register_eventfd(&self, eventfd: i32) -> Result<(), InitError> {
-        let eventfd = eventfd;
-        self.register(
-            IoringRegisterOp::RegisterEventfd,
-            (&eventfd as *const i32).cast::<c_void>(),
+        self.ring_submitter().register_eventfd(eventfd)
+    }
+
+    pub fn unregister_eventfd(&self) -> Result<(), InitError> {
+        self.ring_submitter().unregister_eventfd()
+    }
+
+    pub fn register_eventfd_async(&self, eventfd: i32) -> Result<(), InitError> {
+        self.ring_submitter().register_eventfd_async(eventfd)
+    }
+
+    /// Probe which opcodes the kernel supports
+    ///
+    /// The probe is only queried once per ring and cached afterwards, so
+    /// checking support is cheap enough for hot paths.
+    ///
+    /// ## Errors
+    /// Returns `InitError` if the kernel can't be probed (before 5.6).
+    pub fn probe(&self) -> Result<&Probe, InitError> {
+        if let Some(probe) = self.probe.get() {
+            return Ok(probe);
+        }
+        let probe = self.ring_submitter().probe()?;
+        Ok(self.probe.get_or_init(|| probe))
+    }
+
+    /// Register the ring fd with the calling thread
+    ///
+    /// Once registered, every enter passes the registered index with
+    /// `IORING_ENTER_REGISTERED_RING`, which saves the kernel an fd lookup per
+    /// call. The registration belongs to the calling thread: submitters used
+    /// from other threads keep entering through the real fd. Returns the
+    /// registered index; calling this again returns the existing index.
+    ///
+    /// ## Errors
+    /// Returns `InitError` if the registration fails.
+    pub fn register_ring_fd(&mut self) -> Result<u32, InitError> {
+        if let Some(ring_index) = self.ring_index {
+            return Ok(ring_index.index());
+        }
+        let fd = self.fd.as_ref().ok_or(InitError::InvalidParameters)?;
+        let data = u64::try_from(fd.as_raw_fd()).map_err(|_| InitError::InvalidParameters)?;
+
+        let mut update = io_uring::io_uring_rsrc_update {
+            // Let the kernel pick a free slot
+            offset: u32::MAX,
+            resv: 0,
+            data,
+        };
+        self.ring_submitter().register(
+            IoringRegisterOp::RegisterRingFds,
+            (&mut update as *mut io_uring::io_uring_rsrc_update).cast::<c_void>(),
             1,
         )?;
-        Ok(())
+
+        self.ring_index = Some(RingIndex::new(update.offset));
+        Ok(update.offset)
     }
 
-    pub fn unregister_eventfd(&self) -> Result<(), InitError> {
-        self.register(IoringRegisterOp::UnregisterEventfd, null(), 0)?;
+    /// Unregister the ring fd registered with [`register_ring_fd`](Self::register_ring_fd)
+    ///
+    /// ## Errors
+    /// Returns `InitError::InvalidParameters` if the ring fd isn't registered
+    /// or the real fd was already closed, since the ring would be unreachable
+    /// afterwards.
+    pub fn unregister_ring_fd(&mut self) -> Result<(), InitError> {
+        let index = self
+            .ring_index
+            .map(RingIndex::index)
+            .ok_or(InitError::InvalidParameters)?;
+        if self.fd.is_none() {
+            return Err(InitError::InvalidParameters);
+        }
+
+        self.unregister_ring_index(index)?;
+        self.ring_index = None;
         Ok(())
     }
 
-    pub fn register_eventfd_async(&self, eventfd: i32) -> Result<(), InitError> {
-        let eventfd = eventfd;
-        self.register(
-            IoringRegisterOp::RegisterEventfdAsync,
-            (&eventfd as *const i32).cast::<c_void>(),
+    fn unregister_ring_index(&self, index: u32) -> Result<(), InitError> {
+        let update = io_uring::io_uring_rsrc_update {
+            offset: index,
+            resv: 0,
+            data: 0,
+        };
+        self.ring_submitter().register(
+            IoringRegisterOp::UnregisterRingFds,
+            (&update as *const io_uring::io_uring_rsrc_update).cast::<c_void>(),
             1,
         )?;
         Ok(())
     }
 
-    pub fn probe(&self) -> Result<Probe, InitError> {
-        let mut probe = Probe::new();
-        let nr_args = PROBE_OPS as u32;
-        let arg = &mut probe as *mut Probe as *const c_void;
+    /// Close the real ring fd, keeping only the registered one
+    ///
+    /// Afterwards the ring can only be used from the thread that registered
+    /// it, other threads getting `EBADF`, and registering resources requires
+    /// Linux 6.3 or later. Without the `std` feature, submitters from
+    /// [`submitter`](Self::submitter) and [`split`](Self::split) get `EBADF`
+    /// on every thread.
+    /// [`as_raw_fd`](AsRawFd::as_raw_fd) returns `-1` from then on.
+    ///
+    /// ## Errors
+    /// Returns `InitError::InvalidParameters` if the ring fd isn't registered.
+    pub fn close_ring_fd(&mut self) -> Result<(), InitError> {
+        if self.ring_index.is_none() {
+            return Err(InitError::InvalidParameters);
+        }
+        self.fd = None;
+        Ok(())
+    }
 
-        self.register(IoringRegisterOp::RegisterProbe, arg, nr_args)?;
+    /// The ring fd, unless it was closed with [`close_ring_fd`](Self::close_ring_fd)
+    pub(crate) fn ring_fd(&self) -> Option<&OwnedFd> {
+        self.fd.as_ref()
+    }
 
-        Ok(probe)
+    /// Index of the registered ring fd, if any
+    #[must_use]
+    pub fn registered_ring_fd(&self) -> Option<u32> {
+        self.ring_index.map(RingIndex::index)
     }
 
     #[must_use]
@@ -431,6 +870,12 @@ impl IoUring {
         }
     }
 
+    /// Whether the kernel supports `opcode`, from the cached probe
+    #[must_use]
+    pub fn supports(&self, opcode: Opcode) -> bool {
+        self.opcode_supported(opcode.into())
+    }
+
     // Feature detection methods
 
     /// Get the supported features bitmask from the kernel
@@ -565,15 +1010,26 @@ impl IoUring {
 
     // Kernel version detection helpers
 
-    /// Get the kernel version that io_uring was compiled for
+    /// Get the version of the running kernel
     ///
-    /// Returns a tuple of (major, minor, patch) representing the kernel version
-    /// detected during setup. This is parsed from the setup parameters.
+    /// Returns a tuple of (major, minor, patch) parsed from `uname` when the
+    /// ring was created, or `(0, 0, 0)` if the release string is unusual.
     #[must_use]
     pub fn kernel_version(&self) -> (u32, u32, u32) {
-        // rustix doesn't expose version fields directly, use feature detection instead
-        // We can infer minimum kernel version from feature support
-        (5, 4, 0) // Default to a reasonable minimum
+        self.kernel_version
+    }
+
+    /// Detect what the kernel supports
+    ///
+    /// Combines the kernel version, the setup features and an opcode probe.
+    /// Probing enters the kernel, so call this once at startup.
+    #[must_use]
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::new(
+            self.kernel_version,
+            self.features(),
+            self.probe().ok().cloned(),
+        )
     }
 
     /// Check if kernel version is at least the specified version
@@ -619,10 +1075,10 @@ impl IoUring {
 
     /// Check if kernel version supports fixed buffers
     ///
-    /// Returns true if kernel version is at least 5.7.0 (fixed buffers).
+    /// Returns true if kernel version is at least 5.1.0 (fixed buffers).
     #[must_use]
     pub fn has_fixed_buffers(&self) -> bool {
-        self.kernel_version_at_least(5, 7, 0)
+        self.kernel_version_at_least(5, 1, 0)
     }
 
     /// Check if kernel version supports eventfd notifications
@@ -651,24 +1107,58 @@ impl IoUring {
 
     /// Check if kernel version supports extended SQE/CQE formats
     ///
-    /// Returns true if kernel version is at least 5.11.0 (SQE128/CQE32).
+    /// Returns true if kernel version is at least 5.19.0 (SQE128/CQE32).
     #[must_use]
     pub fn has_extended_formats(&self) -> bool {
-        self.kernel_version_at_least(5, 11, 0)
+        self.kernel_version_at_least(5, 19, 0)
     }
 
     #[must_use]
     pub fn get_sqe(&mut self) -> Option<&mut io_uring_sqe> {
+        if self.sq.is_sqe128() {
+            return self.get_sqe128().map(|sqe| &mut sqe.sqe);
+        }
+
         let sqe = self.sq.peek_sqe()?;
         *sqe = io_uring_sqe::default();
         Some(sqe)
     }
 
+    /// Get a zeroed 128-byte SQE
+    ///
+    /// Returns `None` if the SQ is full or the ring was not built with
+    /// [`SetupBuilder::sqe128`].
+    #[must_use]
+    pub fn get_sqe128(&mut self) -> Option<&mut crate::io_uring_sqe128> {
+        let sqe = self.sq.peek_sqe128()?;
+        *sqe = crate::io_uring_sqe128::default();
+        Some(sqe)
+    }
+
+    /// Queue a prepared entry
+    ///
+    /// ## Errors
+    /// Returns `PushError` if the SQ is full, or if the entry can't be
+    /// tracked by [`SetupBuilder::cap_inflight`].
+    pub fn push(&mut self, entry: &crate::sqe::Entry) -> Result<(), PushError> {
+        self.sq.push(entry)
+    }
+
+    /// Queue several prepared entries, all or nothing
+    ///
+    /// ## Errors
+    /// Returns `PushError` if the SQ lacks space for every entry, or as for
+    /// [`push`](Self::push).
+    pub fn push_multiple(&mut self, entries: &[crate::sqe::Entry]) -> Result<(), PushError> {
+        self.sq.push_multiple(entries)
+    }
+
     /// Get an SQE with reclaim support for better performance
     ///
     /// This method first tries to get a cached SQE, falling back to
     /// a fresh SQE if the cache is empty. The returned SQE should be
     /// reclaimed using `reclaim_sqe()` when the operation completes.
+    #[cfg(feature = "alloc")]
     #[must_use]
     pub fn get_sqe_with_reclaim(&mut self) -> Option<&mut io_uring_sqe> {
         // Try to get a cached SQE first
@@ -687,6 +1177,7 @@ impl IoUring {
     ///
     /// This should be called after processing the corresponding CQE
     /// to reuse the SQE for future operations and avoid initialization overhead.
+    #[cfg(feature = "alloc")]
     pub fn reclaim_sqe(&mut self, sqe: &mut io_uring_sqe) {
         // Take a pointer to SQE before reclaiming it to avoid borrow issues
         let sqe_ptr = sqe as *mut io_uring_sqe;
@@ -695,6 +1186,21 @@ impl IoUring {
 
     // Linked operation helpers
 
+    /// Start a chain of linked entries, queued all at once
+    ///
+    /// See [`Chain`](crate::Chain).
+    #[cfg(feature = "alloc")]
+    pub fn chain(&mut self) -> crate::Chain<'_> {
+        crate::Chain::new(self)
+    }
+
+    /// Number for the next chain queued, wrapping around
+    #[cfg(feature = "alloc")]
+    pub(crate) fn next_chain(&mut self) -> u32 {
+        self.chains = self.chains.wrapping_add(1);
+        self.chains
+    }
+
     /// Mark an SQE as linked with the next SQE
     ///
     /// The next SQE submitted will only execute if this SQE succeeds.
@@ -754,38 +1260,26 @@ impl IoUring {
     /// Allocate a unique user_data value for SQE tracking
     ///
     /// Returns a unique value that can be used to identify operations
-    /// through their corresponding CQEs. The allocator reuses freed
-    /// values to avoid overflow.
+    /// through their corresponding CQEs. Values range over
+    /// `1..=cq_entries`, one per CQE the ring can hold, and freed values are
+    /// handed out again first. Returns `None` once every value is in use.
+    ///
+    /// Freed values are reused as-is, so a late CQE can't be told apart from
+    /// one for the value's next owner; [`OpSlab`](crate::OpSlab) tokens can.
+    #[cfg(feature = "alloc")]
     #[must_use]
-    pub fn alloc_user_data(&self) -> u64 {
-        // Try to reuse a freed user_data first
-        if let Some(reused) = self.free_user_data.borrow_mut().pop() {
-            return reused;
-        }
-
-        // Allocate new user_data, wrapping around if we reach u64::MAX
-        let current = self
-            .next_user_data
-            .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
-        if current == 0 {
-            // If we wrapped back to 0, use 1 to avoid reserved value
-            self.next_user_data
-                .store(2, core::sync::atomic::Ordering::Relaxed);
-            1
-        } else {
-            current
-        }
+    pub fn alloc_user_data(&self) -> Option<u64> {
+        self.user_data.alloc()
     }
 
     /// Free a user_data value for reuse
     ///
     /// This should be called after processing the corresponding CQE
     /// to allow the user_data value to be reused for future operations.
+    /// Values that aren't currently allocated are ignored.
+    #[cfg(feature = "alloc")]
     pub fn free_user_data(&self, user_data: u64) {
-        // Don't add 0 to free list as it's a reserved value
-        if user_data != 0 {
-            self.free_user_data.borrow_mut().push(user_data);
-        }
+        self.user_data.free(user_data);
     }
 
     /// Set user_data on an SQE using the allocator
@@ -799,27 +1293,24 @@ impl IoUring {
     }
 
     /// Get the number of currently allocated user_data values
+    #[cfg(feature = "alloc")]
     #[must_use]
     pub fn allocated_user_data_count(&self) -> usize {
-        // Total allocated = next_user_data - freed_count
-        let next_val = self
-            .next_user_data
-            .load(core::sync::atomic::Ordering::Relaxed);
-        let freed_count = self.free_user_data.borrow().len();
-
-        if next_val == 0 {
-            0
-        } else if next_val == 1 {
-            0
-        } else {
-            (next_val as usize).saturating_sub(freed_count)
-        }
+        self.user_data.allocated() as usize
     }
 
     /// Get the number of freed user_data values available for reuse
+    #[cfg(feature = "alloc")]
     #[must_use]
     pub fn available_user_data_count(&self) -> usize {
-        self.free_user_data.borrow().len()
+        self.user_data.available() as usize
+    }
+
+    /// Total number of user_data values the allocator can hand out
+    #[cfg(feature = "alloc")]
+    #[must_use]
+    pub fn user_data_capacity(&self) -> usize {
+        self.user_data.capacity() as usize
     }
 
     // Multi-shot operation support
@@ -848,40 +1339,72 @@ impl IoUring {
     /// Setup a multi-shot poll operation
     ///
     /// Multi-shot operations generate multiple CQEs without resubmission.
-    /// The operation continues until explicitly cancelled.
-    /// Setup a multi-shot poll operation
-    ///
-    /// Multi-shot operations generate multiple CQEs without resubmission.
-    /// The operation continues until explicitly cancelled.
+    /// The operation continues until explicitly cancelled, or until the
+    /// kernel ends it with a CQE lacking `IORING_CQE_F_MORE`; see
+    /// [`rearm_multishot`](Self::rearm_multishot).
     ///
-    /// Requires IORING_FEAT_POLL_32BITS feature for 32-bit event masks.
+    /// Requires Linux 5.13.
     #[must_use]
     pub fn poll_add_multishot(&mut self, fd: i32, events: u16) -> Option<&mut io_uring_sqe> {
-        // Check if we support 32-bit poll events
-        if events as u32 > 0xFFFF && !self.has_poll_32bits() {
-            return None;
-        }
-
-        let sqe = self.get_sqe()?;
-        crate::sqe::PollAdd::new(fd, events).prep(sqe);
-        // Multi-shot poll requires IOSQE_ASYNC flag
-        sqe.flags |= crate::IOSQE_ASYNC;
-        Some(sqe)
+        let mut poll = crate::sqe::PollAdd::new(fd, events);
+        poll.set_multishot();
+        self.prepare(&poll)
     }
 
     /// Setup a multi-shot accept operation
     ///
-    /// Multi-shot accept generates multiple CQEs for each incoming connection
-    /// without resubmission. The operation continues until explicitly cancelled.
+    /// Multi-shot accept generates a CQE for each incoming connection
+    /// without resubmission. The operation continues until explicitly
+    /// cancelled, or until the kernel ends it with a CQE lacking
+    /// `IORING_CQE_F_MORE`.
     ///
-    /// Requires IORING_FEAT_FAST_POLL feature for optimal performance.
+    /// Requires Linux 5.19.
     #[must_use]
     pub fn accept_multishot(&mut self, fd: i32, flags: i32) -> Option<&mut io_uring_sqe> {
-        let sqe = self.get_sqe()?;
-        crate::sqe::Accept::new(fd, flags | crate::SOCK_CLOEXEC).prep(sqe);
-        // Multi-shot accept requires IOSQE_ASYNC flag
-        sqe.flags |= crate::IOSQE_ASYNC;
-        Some(sqe)
+        let mut accept = crate::sqe::Accept::new(fd, flags | crate::SOCK_CLOEXEC);
+        accept.set_multishot();
+        self.prepare_mut(&mut accept)
+    }
+
+    /// Setup a multi-shot receive into the provided buffer group `buf_group`
+    ///
+    /// See [`RecvMulti`](crate::sqe::RecvMulti). Requires Linux 6.0.
+    #[must_use]
+    pub fn recv_multishot(
+        &mut self,
+        fd: i32,
+        buf_group: u16,
+        flags: i32,
+    ) -> Option<&mut io_uring_sqe> {
+        self.prepare(&crate::sqe::RecvMulti::new(fd, buf_group, flags))
+    }
+
+    /// Queue `entry` again if `cqe` ended the multishot operation it started
+    ///
+    /// A multishot operation keeps running while its CQEs carry
+    /// `IORING_CQE_F_MORE`. Once one arrives without it, for instance
+    /// because a receive ran out of buffers (`-ENOBUFS`), the operation is
+    /// over and must be submitted again. Operations ended by cancellation
+    /// (`-ECANCELED`) are left alone. Returns whether `entry` was queued.
+    ///
+    /// ## Errors
+    /// Returns `RearmError::QueueFull` if the SQ is full, or
+    /// `RearmError::Failed` if the operation ended with any other error,
+    /// such as `-EBADF`, which a new submission would run into again.
+    pub fn rearm_multishot(
+        &mut self,
+        entry: &crate::sqe::Entry,
+        cqe: &crate::io_uring_cqe,
+    ) -> Result<bool, RearmError> {
+        if self.cqe_has_more(cqe) || cqe.res == -Errno::CANCELED.raw_os_error() {
+            return Ok(false);
+        }
+        if cqe.res < 0 && cqe.res != -Errno::NOBUFS.raw_os_error() {
+            return Err(RearmError::Failed(Errno::from_raw_os_error(-cqe.res)));
+        }
+
+        self.push(entry)?;
+        Ok(true)
     }
 
     /// Cancel a multi-shot operation
@@ -897,8 +1420,8 @@ impl IoUring {
     #[doc = "## Errors"]
     #[doc = "Returns `EnterError` if the submit fails."]
     pub fn submit(&mut self) -> Result<usize, EnterError> {
-        let to_submit = self.sq.update_kernel_tail();
-        let result = self.enter(to_submit, 0, 0, None);
+        self.sq.update_kernel_tail();
+        let result = self.ring_submitter().submit();
         self.sq.update_from_kernel();
         self.cq.update_kernel_tail();
         result
@@ -909,18 +1432,60 @@ impl IoUring {
     #[doc = "## Errors"]
     #[doc = "Returns `EnterError` if the submit fails."]
     pub fn submit_and_wait(&mut self, wait_count: usize) -> Result<usize, EnterError> {
-        let to_submit = self.sq.update_kernel_tail();
-        let result = self.enter(
-            to_submit,
-            wait_count as u32,
-            crate::IORING_ENTER_GETEVENTS,
-            None,
-        );
+        self.sq.update_kernel_tail();
+        let result = self.ring_submitter().submit_and_wait(wait_count);
         self.sq.update_from_kernel();
         self.cq.update_kernel_tail();
         result
     }
 
+    /// Overflow state of the completion queue
+    #[must_use]
+    pub fn overflow_status(&self) -> OverflowStatus {
+        OverflowStatus {
+            backlogged: self.sq.cq_overflow(),
+            dropped: self.cq.overflow_count(),
+        }
+    }
+
+    /// Flush backlogged CQEs into the CQ ring
+    ///
+    /// Only enters the kernel if CQEs are backlogged. The kernel moves as many
+    /// as fit, so reap the CQ and call this again while
+    /// [`OverflowStatus::backlogged`] is set.
+    ///
+    /// ## Errors
+    /// Returns `EnterError` if the enter fails.
+    pub fn flush_overflow(&mut self) -> Result<OverflowStatus, EnterError> {
+        if self.sq.cq_overflow() {
+            self.enter(0, 0, crate::IORING_ENTER_GETEVENTS, None)?;
+            self.cq.update_kernel_tail();
+        }
+        Ok(self.overflow_status())
+    }
+
+    /// Number of pushed entries whose last CQE hasn't been reaped yet
+    ///
+    /// Only tracked on rings set up with [`SetupBuilder::cap_inflight`];
+    /// `None` otherwise.
+    #[must_use]
+    pub fn inflight(&self) -> Option<u32> {
+        self.sq.inflight()
+    }
+
+    /// Wait for the SQ poll thread to make room in a full SQ
+    ///
+    /// Uses `IORING_ENTER_SQ_WAIT`, so it only blocks on rings set up with
+    /// [`SetupBuilder::sqpoll`].
+    ///
+    /// ## Errors
+    /// Returns `EnterError` if the enter fails.
+    pub fn squeue_wait(&mut self) -> Result<usize, EnterError> {
+        let result = self.ring_submitter().squeue_wait();
+        self.sq.update_from_kernel();
+        result
+    }
+
     #[doc = "Enter the io_uring with the specified parameters."]
     #[doc = ""]
     #[doc = "## Errors"]
@@ -932,23 +1497,8 @@ impl IoUring {
         flags: u32,
         sig: Option<&sigset_t>,
     ) -> Result<usize, EnterError> {
-        let sigmask_ptr: *mut sigset_t =
-            sig.map_or(null_mut(), |s| s as *const sigset_t as *mut sigset_t);
-        let sigmask_size = sig.map_or(0, |_| core::mem::size_of::<sigset_t>());
-
-        // SAFETY: io_uring_enter is safe to call with these parameters
-        let submitted: u32 = unsafe {
-            rustix::io_uring::io_uring_enter(
-                self.fd.as_fd(),
-                to_submit,
-                wait_count,
-                IoringEnterFlags::from_bits(flags).unwrap_or(IoringEnterFlags::empty()),
-                sigmask_ptr.cast::<core::ffi::c_void>(),
-                sigmask_size,
-            )
-        }?;
-
-        Ok(submitted as usize)
+        self.ring_submitter()
+            .enter(to_submit, wait_count, flags, sig)
     }
 
     /// Enter the io_uring with extended arguments
@@ -966,28 +1516,8 @@ impl IoUring {
         arg: &crate::io_uring_getevents_arg,
         sig: Option<&sigset_t>,
     ) -> Result<usize, EnterError> {
-        // Only allow extended args if feature is supported
-        if !self.has_ext_arg() {
-            return Err(EnterError::UnsupportedOperation);
-        }
-
-        let sigmask_ptr: *mut sigset_t =
-            sig.map_or(null_mut(), |s| s as *const sigset_t as *mut sigset_t);
-        let sigmask_size = sig.map_or(0, |_| core::mem::size_of::<sigset_t>());
-
-        // SAFETY: io_uring_enter is safe to call with these parameters
-        let submitted: u32 = unsafe {
-            rustix::io_uring::io_uring_enter(
-                self.fd.as_fd(),
-                to_submit,
-                wait_count,
-                IoringEnterFlags::from_bits_retain(flags | crate::IORING_ENTER_EXT_ARG),
-                arg as *const crate::io_uring_getevents_arg as *mut core::ffi::c_void,
-                sigmask_size,
-            )
-        }?;
-
-        Ok(submitted as usize)
+        self.ring_submitter()
+            .enter_ext_arg(to_submit, wait_count, flags, arg, sig)
     }
 
     /// Submit and wait with timeout using extended arguments
@@ -1020,23 +1550,45 @@ impl IoUring {
 
     #[must_use]
     pub fn peek_cqe(&mut self) -> Option<&crate::io_uring_cqe> {
-        self.cq.update_kernel_tail();
+        self.update_cq();
         self.cq.peek()
     }
 
-    pub fn copy_cqes(&mut self, count: usize) -> &[crate::io_uring_cqe] {
-        self.cq.update_kernel_tail();
-        let available = self.cq.events_available() as usize;
-        let to_copy = count.min(available);
+    /// Peek at the next 32-byte CQE
+    ///
+    /// Returns `None` if the CQ is empty or the ring was not built with
+    /// [`SetupBuilder::cqe32`].
+    #[must_use]
+    pub fn peek_cqe32(&mut self) -> Option<&crate::io_uring_cqe32> {
+        self.update_cq();
+        self.cq.peek32()
+    }
 
-        if to_copy == 0 {
-            return &[];
+    /// Pick up the kernel's CQ tail, first entering the ring to run deferred
+    /// task work or flush backlogged CQEs if the CQ is otherwise empty
+    fn update_cq(&mut self) {
+        self.cq.update_kernel_tail();
+        if self.cq.is_empty() && self.ring_submitter().cq_needs_enter() {
+            // Errors surface on the next submit; an empty CQ is all we report.
+            let _ = self.enter(0, 0, crate::IORING_ENTER_GETEVENTS, None);
+            self.cq.update_kernel_tail();
         }
+    }
 
-        let head = self.cq.get_khead() as usize;
+    /// Take a batch of all available CQEs
+    ///
+    /// See [`CompletionQueue::completions`].
+    pub fn completions(&mut self) -> Completions<'_> {
+        self.update_cq();
+        self.cq.completions()
+    }
 
-        // SAFETY: We're reading from our own mapped memory
-        unsafe { core::slice::from_raw_parts(self.cq.cqe_ptr().add(head), to_copy) }
+    /// Copy up to `cqes.len()` available CQEs into `cqes` and consume them
+    ///
+    /// Returns the number of entries copied.
+    pub fn copy_cqes(&mut self, cqes: &mut [crate::io_uring_cqe]) -> usize {
+        self.update_cq();
+        self.cq.copy_cqes(cqes)
     }
 
     pub fn cqe_seen(&mut self, _cqe: &crate::io_uring_cqe) {
@@ -1374,6 +1926,27 @@ impl IoUring {
         ))
     }
 
+    #[must_use]
+    pub fn socket(&mut self, domain: i32, ty: i32, protocol: i32) -> Option<&mut io_uring_sqe> {
+        self.prepare(&crate::sqe::Socket::new(domain, ty, protocol))
+    }
+
+    /// Create a socket straight into a fixed file slot
+    ///
+    /// `file_index` is as for [`Socket::set_file_index`](crate::sqe::Socket::set_file_index).
+    #[must_use]
+    pub fn socket_direct(
+        &mut self,
+        domain: i32,
+        ty: i32,
+        protocol: i32,
+        file_index: u32,
+    ) -> Option<&mut io_uring_sqe> {
+        let mut socket = crate::sqe::Socket::new(domain, ty, protocol);
+        socket.set_file_index(file_index);
+        self.prepare(&socket)
+    }
+
     #[must_use]
     pub fn connect(&mut self, fd: i32, addr: &[u8], addrlen: u32) -> Option<&mut io_uring_sqe> {
         self.prepare(&crate::sqe::Connect::new(fd, addr, addrlen))
@@ -1465,13 +2038,19 @@ impl IoUring {
 
 impl rustix::fd::AsRawFd for IoUring {
     fn as_raw_fd(&self) -> i32 {
-        self.fd.as_raw_fd()
+        self.fd.as_ref().map_or(-1, AsRawFd::as_raw_fd)
     }
 }
 
 impl Drop for IoUring {
     fn drop(&mut self) {
+        // A registered ring fd holds a reference to the ring until the
+        // registering thread exits, so release it explicitly. The ring isn't
+        // `Send`, so this is the registering thread.
+        if let Some(index) = self.ring_index.map(RingIndex::index) {
+            let _ = self.unregister_ring_index(index);
+        }
         // The file descriptor and memory mappings will be automatically
-        // cleaned up when OwnedFd and RwMmap are dropped
+        // cleaned up when OwnedFd and RingMemory are dropped
     }
 }
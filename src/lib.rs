@@ -1,31 +1,75 @@
+#![cfg_attr(not(test), no_std)]
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(dead_code, unused_imports)]
 #![allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
 use core::ffi::c_void;
 use core::sync::atomic::AtomicU32;
 
 use rustix::fd::RawFd;
 
+#[cfg(feature = "alloc")]
+pub mod buf_ring;
+pub mod caps;
+#[cfg(feature = "alloc")]
+pub mod chain;
 pub mod cq;
 pub mod cqe;
 pub mod err;
+#[cfg(feature = "alloc")]
+pub mod fixed;
 pub mod io_uring;
 pub mod mmap;
+pub mod opcode;
+#[cfg(feature = "alloc")]
+pub mod owned;
+#[cfg(feature = "alloc")]
+pub mod restrict;
+pub mod rsrc;
+#[cfg(feature = "alloc")]
+mod slab;
 pub mod sq;
 pub mod sqe;
-#[cfg(test)]
+pub mod submit;
+#[cfg(feature = "alloc")]
+pub mod token;
+// The tests lean on std types such as files and threads
+#[cfg(all(test, feature = "std"))]
 mod tests;
 
-pub use cq::CompletionQueue;
+#[cfg(feature = "alloc")]
+pub use buf_ring::{Buf, BufRing};
+pub use caps::{Capabilities, Capability};
+#[cfg(feature = "alloc")]
+pub use chain::{Chain, ChainHandle};
+pub use cq::{CompletionQueue, Completions, OverflowStatus};
 pub use cqe::CqeFlags;
-pub use err::{EnterError, InitError, IoUringResult};
+pub use err::{
+    ChainError, EnterError, InitError, IoUringResult, PushError, RearmError, UnknownOpcode,
+};
+#[cfg(feature = "alloc")]
+pub use fixed::{FixedBuf, FixedBufferPool, FixedFileTable};
 pub use io_uring::{IoUring, Probe, SetupBuilder};
-pub use mmap::RwMmap;
+pub use mmap::{RingMemory, RwMmap, UserRingMemory};
+pub use opcode::Opcode;
+#[cfg(feature = "alloc")]
+pub use owned::{Completion, OwnedOp, OwnedOps, Reaped};
+#[cfg(feature = "alloc")]
+pub use restrict::RestrictionSet;
+pub use rsrc::{RsrcEvent, RsrcTag};
 pub use sq::SubmissionQueue;
 pub use sqe::{
-    sqe_flags, Accept, Connect, Iovec, MsgHdr, Recv, RecvMsg, Send, SendMsg, Shutdown, SqeFlags,
+    sqe_flags, Accept, Connect, Entry, FixedFd, Iovec, MsgHdr, Recv, RecvMsg, Send, SendMsg,
+    Shutdown, Socket, SqeFlags,
 };
+pub use submit::Submitter;
+#[cfg(feature = "alloc")]
+pub use token::{OpSlab, Token};
 
 pub const IORING_SETUP_IOPOLL: u32 = 1 << 0;
 pub const IORING_SETUP_SQPOLL: u32 = 1 << 1;
@@ -39,6 +83,9 @@ pub const IORING_SETUP_COOP_TASKRUN: u32 = 1 << 8;
 pub const IORING_SETUP_TASKRUN_FLAG: u32 = 1 << 9;
 pub const IORING_SETUP_SQE128: u32 = 1 << 10;
 pub const IORING_SETUP_CQE32: u32 = 1 << 11;
+pub const IORING_SETUP_SINGLE_ISSUER: u32 = 1 << 12;
+pub const IORING_SETUP_DEFER_TASKRUN: u32 = 1 << 13;
+pub const IORING_SETUP_NO_MMAP: u32 = 1 << 14;
 
 pub const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
 pub const IORING_ENTER_SQ_WAKEUP: u32 = 1 << 1;
@@ -105,11 +152,28 @@ pub const IORING_OP_SENDMSG_ZC: u8 = 48;
 // Timeout flags
 pub const IORING_TIMEOUT_ABS: u32 = 1 << 0;
 
+// Poll flags, passed in `len` of IORING_OP_POLL_ADD
+pub const IORING_POLL_ADD_MULTI: u32 = 1 << 0;
+
+// Accept flags, passed in `ioprio`
+pub const IORING_ACCEPT_MULTISHOT: u16 = 1 << 0;
+
+// Send/recv flags, passed in `ioprio`
+pub const IORING_RECVSEND_POLL_FIRST: u16 = 1 << 0;
+pub const IORING_RECV_MULTISHOT: u16 = 1 << 1;
+
 // Async cancel flags
 pub const IORING_ASYNC_CANCEL_ALL: u32 = 1 << 0;
 pub const IORING_ASYNC_CANCEL_ANY: u32 = 1 << 1;
 pub const IORING_ASYNC_CANCEL_FD: u32 = 1 << 2;
 
+// Direct descriptor flags
+/// `file_index` asking the kernel to pick a free fixed file slot, returned
+/// as the CQE's `res`
+pub const IORING_FILE_INDEX_ALLOC: u32 = !0;
+/// Most files a ring can register (`IORING_MAX_FIXED_FILES`)
+pub const IORING_MAX_FIXED_FILES: u32 = 1 << 20;
+
 // Buffer ring flags
 pub const IORING_SETUP_BUFFER_RING: u64 = 1 << 3;
 
@@ -128,8 +192,16 @@ pub const IORING_CQE_F_MORE: u32 = 1 << 1;
 pub const IORING_CQE_F_SOCK_NONEMPTY: u32 = 1 << 2;
 pub const IORING_CQE_F_TIMEOUT: u32 = 1 << 3;
 pub const IORING_CQE_F_NOTIFICATION: u32 = 1 << 4;
+/// A buffer ring consumed incrementally kept the CQE's buffer for more data
+/// (Linux 6.12+)
+pub const IORING_CQE_F_BUF_MORE: u32 = 1 << 4;
+
+/// The buffer id of a CQE with `IORING_CQE_F_BUFFER` sits above this bit
+pub const IORING_CQE_BUFFER_SHIFT: u32 = 16;
 
 pub const IORING_SQ_NEED_WAKEUP: u32 = 1 << 0;
+pub const IORING_SQ_CQ_OVERFLOW: u32 = 1 << 1;
+pub const IORING_SQ_TASKRUN: u32 = 1 << 2;
 
 pub const IORING_F_SQE128: u32 = 1 << 0;
 pub const IORING_F_CQE32: u32 = 1 << 1;
@@ -237,10 +309,10 @@ pub const SHUT_RD: i32 = 0;
 pub const SHUT_WR: i32 = 1;
 pub const SHUT_RDWR: i32 = 2;
 
-pub use rustix::io_uring::{io_cqring_offsets, io_sqring_offsets};
+pub use rustix::io_uring::{io_cqring_offsets, io_sqring_offsets, IoringRegisterOp};
 
 #[repr(C)]
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Copy, Clone)]
 pub struct io_uring_sqe {
     pub opcode: u8,
     pub flags: u8,
@@ -266,13 +338,93 @@ impl io_uring_sqe {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Default, Copy, Clone)]
 pub struct io_uring_cqe {
     pub user_data: u64,
     pub res: i32,
     pub flags: u32,
 }
 
+/// Size of the command area of a 128-byte SQE
+pub const IORING_SQE128_CMD_SIZE: usize = 80;
+
+/// 128-byte SQE used by rings set up with `IORING_SETUP_SQE128`
+///
+/// The first 64 bytes are a regular [`io_uring_sqe`]. The command area used
+/// by `IORING_OP_URING_CMD` starts at the `addr3` field and runs to the end
+/// of the entry.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct io_uring_sqe128 {
+    pub sqe: io_uring_sqe,
+    pub(crate) ext: [u8; 64],
+}
+
+impl Default for io_uring_sqe128 {
+    fn default() -> Self {
+        Self {
+            sqe: io_uring_sqe::default(),
+            ext: [0; 64],
+        }
+    }
+}
+
+impl io_uring_sqe128 {
+    const CMD_OFFSET: usize = core::mem::offset_of!(io_uring_sqe, addr3);
+
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The 80-byte command area
+    #[must_use]
+    pub fn cmd(&self) -> &[u8; IORING_SQE128_CMD_SIZE] {
+        // SAFETY: the struct is repr(C) and 128 bytes long with no padding,
+        // so the 80 bytes starting at `addr3` are in bounds and initialized.
+        unsafe {
+            &*core::ptr::from_ref(self)
+                .cast::<u8>()
+                .add(Self::CMD_OFFSET)
+                .cast::<[u8; IORING_SQE128_CMD_SIZE]>()
+        }
+    }
+
+    /// The 80-byte command area, mutably
+    #[must_use]
+    pub fn cmd_mut(&mut self) -> &mut [u8; IORING_SQE128_CMD_SIZE] {
+        // SAFETY: see `cmd`.
+        unsafe {
+            &mut *core::ptr::from_mut(self)
+                .cast::<u8>()
+                .add(Self::CMD_OFFSET)
+                .cast::<[u8; IORING_SQE128_CMD_SIZE]>()
+        }
+    }
+}
+
+/// 32-byte CQE posted by rings set up with `IORING_SETUP_CQE32`
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_cqe32 {
+    pub cqe: io_uring_cqe,
+    pub big_cqe: [u64; 2],
+}
+
+impl io_uring_cqe32 {
+    /// First extra 64-bit result word
+    #[must_use]
+    pub fn extra1(&self) -> u64 {
+        self.big_cqe[0]
+    }
+
+    /// Second extra 64-bit result word
+    #[must_use]
+    pub fn extra2(&self) -> u64 {
+        self.big_cqe[1]
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct Timespec {
@@ -280,15 +432,23 @@ pub struct Timespec {
     pub tv_nsec: i64,
 }
 
-// Restriction structures for advanced registration
+// Restriction entry (`struct io_uring_restriction`); `arg` holds the
+// register opcode, SQE opcode or SQE flags, depending on `opcode`
 #[repr(C)]
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Restriction {
     pub opcode: u16,
-    pub flags: u32,
-    pub resv: [u32; 2],
+    pub arg: u8,
+    pub resv: u8,
+    pub resv2: [u32; 3],
 }
 
+// Restriction kinds, in `Restriction::opcode`
+pub const IORING_RESTRICTION_REGISTER_OP: u16 = 0;
+pub const IORING_RESTRICTION_SQE_OP: u16 = 1;
+pub const IORING_RESTRICTION_SQE_FLAGS_ALLOWED: u16 = 2;
+pub const IORING_RESTRICTION_SQE_FLAGS_REQUIRED: u16 = 3;
+
 #[repr(C)]
 #[derive(Debug, Clone, Default)]
 pub struct Restrictions {
@@ -297,37 +457,102 @@ pub struct Restrictions {
     pub disallowed_ops: Option<&'static [Restriction]>,
     pub allowed_sqe_flags: Option<u32>,
     pub allowed_file_flags: Option<u32>,
-    pub registerd_files: Option<Vec<i32>>,
+    #[cfg(feature = "alloc")]
+    pub registerd_files: Option<alloc::vec::Vec<i32>>,
 }
 
-// Per-buffer ring entry
+// Provided buffer ring entry (`struct io_uring_buf`); the ring's tail
+// overlays `resv` of the first entry
 #[repr(C)]
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct PbufRingEntry {
-    pub user_addr: u64,
-    pub user_data: u64,
-    pub len: u16,
-    pub bgid: u16,
+    pub addr: u64,
+    pub len: u32,
     pub bid: u16,
+    pub resv: u16,
 }
 
-// Per-buffer ring registration arguments
+/// `PbufRingReg::flags`: the kernel allocates the ring, to be mmapped by the
+/// application
+pub const IOU_PBUF_RING_MMAP: u16 = 1;
+/// `PbufRingReg::flags`: buffers are consumed incrementally (Linux 6.12+)
+pub const IOU_PBUF_RING_INC: u16 = 2;
+
+// Provided buffer ring registration arguments (`struct io_uring_buf_reg`)
 #[repr(C)]
 #[derive(Debug, Clone, Default)]
 pub struct PbufRingReg {
     pub ring_addr: u64,
-    pub ring_len: u32,
+    pub ring_entries: u32,
     pub bgid: u16,
-    pub entry_count: u32,
-    pub entry_size: u32,
+    pub flags: u16,
+    pub resv: [u64; 3],
 }
 
-// Worker thread configuration arguments
+/// io-wq worker limits, per NUMA node, for `IORING_REGISTER_IOWQ_MAX_WORKERS`
+///
+/// `bounded` workers run requests on regular files and block devices,
+/// `unbounded` ones requests that may block indefinitely, like sockets.
+/// A zero limit is left unchanged.
 #[repr(C)]
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct IowqMaxWorkers {
-    pub count: u32,
-    val: u32,
+    pub bounded: u32,
+    pub unbounded: u32,
+}
+
+/// CPU mask for `IORING_REGISTER_IOWQ_AFF`, laid out like `cpu_set_t`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuSet {
+    bits: [u64; 16],
+}
+
+impl CpuSet {
+    /// Number of CPUs a set can hold
+    pub const MAX_CPUS: usize = 1024;
+
+    /// An empty set
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `cpu` to the set
+    ///
+    /// Returns `false`, leaving the set unchanged, if `cpu` is not below
+    /// [`MAX_CPUS`](Self::MAX_CPUS).
+    pub fn set(&mut self, cpu: usize) -> bool {
+        let Some(word) = self.bits.get_mut(cpu / 64) else {
+            return false;
+        };
+        *word |= 1 << (cpu % 64);
+        true
+    }
+
+    /// Remove `cpu` from the set
+    pub fn unset(&mut self, cpu: usize) {
+        if let Some(word) = self.bits.get_mut(cpu / 64) {
+            *word &= !(1 << (cpu % 64));
+        }
+    }
+
+    /// Whether `cpu` is in the set
+    #[must_use]
+    pub fn is_set(&self, cpu: usize) -> bool {
+        self.bits
+            .get(cpu / 64)
+            .is_some_and(|word| word & (1 << (cpu % 64)) != 0)
+    }
+
+    /// Number of CPUs in the set
+    #[must_use]
+    pub fn count(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
 }
 
 #[repr(C)]
@@ -364,10 +589,26 @@ impl iovec {
 
 pub trait PrepSqe {
     fn prep(&self, sqe: &mut io_uring_sqe);
+
+    /// Prepare this operation into an owned [`sqe::Entry`]
+    #[must_use]
+    fn build(&self) -> sqe::Entry {
+        let mut sqe = io_uring_sqe::default();
+        self.prep(&mut sqe);
+        sqe::Entry(sqe)
+    }
 }
 
 pub trait PrepSqeMut {
     fn prep(&mut self, sqe: &mut io_uring_sqe);
+
+    /// Prepare this operation into an owned [`sqe::Entry`]
+    #[must_use]
+    fn build(&mut self) -> sqe::Entry {
+        let mut sqe = io_uring_sqe::default();
+        self.prep(&mut sqe);
+        sqe::Entry(sqe)
+    }
 }
 
 #[must_use]
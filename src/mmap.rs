@@ -3,9 +3,11 @@ use core::ptr::NonNull;
 
 use rustix::fd::{AsFd, BorrowedFd, RawFd};
 use rustix::io::{self, Errno};
+use rustix::io_uring::io_uring_params;
 use rustix::mm::{MapFlags, ProtFlags};
 
 use crate::err::InitError;
+use crate::{io_uring_sqe, IORING_OFF_CQ_RING, IORING_OFF_SQES, IORING_OFF_SQ_RING};
 
 pub struct RwMmap {
     ptr: NonNull<c_void>,
@@ -43,6 +45,26 @@ impl RwMmap {
         })
     }
 
+    /// Map `size` bytes of zeroed private memory
+    pub(crate) fn anonymous(size: usize) -> Result<Self, InitError> {
+        // SAFETY: a fresh anonymous mapping doesn't alias anything
+        let addr = unsafe {
+            rustix::mm::mmap_anonymous(
+                core::ptr::null_mut(),
+                size,
+                ProtFlags::READ | ProtFlags::WRITE,
+                MapFlags::PRIVATE,
+            )
+        }
+        .map_err(InitError::MmapFailed)?;
+
+        Ok(Self {
+            ptr: NonNull::new(addr).ok_or(InitError::MmapFailed(Errno::INVAL))?,
+            size,
+            writable: true,
+        })
+    }
+
     #[must_use]
     pub fn as_ptr(&self) -> *mut c_void {
         self.ptr.as_ptr()
@@ -84,3 +106,180 @@ impl Drop for RwMmap {
         let _ = unsafe { rustix::mm::munmap(self.ptr.as_ptr(), self.size) };
     }
 }
+
+/// Application-provided memory for a ring set up with `IORING_SETUP_NO_MMAP`
+///
+/// `rings` holds the SQ and CQ rings and `sqes` the SQE array. Backing them
+/// with huge pages keeps the rings in as few TLB entries as possible. Use
+/// [`SetupBuilder::no_mmap_sizes`](crate::SetupBuilder::no_mmap_sizes) to find
+/// out how large each region must be.
+///
+/// Neither `Clone` nor `Copy`: each description of the memory sets up one
+/// ring at most.
+#[derive(Debug)]
+pub struct UserRingMemory {
+    rings: NonNull<u8>,
+    rings_size: usize,
+    sqes: NonNull<u8>,
+    sqes_size: usize,
+}
+
+// SAFETY: `UserRingMemory::new` requires the regions to be dedicated to the
+// ring, so the pointers can be handed to whichever thread builds it.
+unsafe impl Send for UserRingMemory {}
+unsafe impl Sync for UserRingMemory {}
+
+impl UserRingMemory {
+    /// Describe the memory regions to set a ring up in
+    ///
+    /// # Safety
+    /// Both regions must be page-aligned, readable and writable for their
+    /// whole size, and must not overlap. They must not be used for anything
+    /// else and must stay valid until the ring built from them is dropped.
+    #[must_use]
+    pub unsafe fn new(
+        rings: NonNull<u8>,
+        rings_size: usize,
+        sqes: NonNull<u8>,
+        sqes_size: usize,
+    ) -> Self {
+        Self {
+            rings,
+            rings_size,
+            sqes,
+            sqes_size,
+        }
+    }
+
+    #[must_use]
+    pub fn rings_size(&self) -> usize {
+        self.rings_size
+    }
+
+    #[must_use]
+    pub fn sqes_size(&self) -> usize {
+        self.sqes_size
+    }
+
+    pub(crate) fn rings_addr(&self) -> u64 {
+        self.rings.as_ptr() as u64
+    }
+
+    pub(crate) fn sqes_addr(&self) -> u64 {
+        self.sqes.as_ptr() as u64
+    }
+}
+
+enum Backing {
+    // SQ ring, CQ ring and SQEs each in their own mapping
+    Separate {
+        sq: RwMmap,
+        cq: RwMmap,
+        sqes: RwMmap,
+    },
+    // SQ and CQ rings share one mapping (`IORING_FEAT_SINGLE_MMAP`)
+    Single {
+        rings: RwMmap,
+        sqes: RwMmap,
+    },
+    // Provided by the application (`IORING_SETUP_NO_MMAP`)
+    User(UserRingMemory),
+}
+
+/// Memory backing the SQ ring, CQ ring and SQE array of a ring
+///
+/// Either mapped from the ring fd, with the SQ and CQ rings sharing a single
+/// mapping when the kernel supports `IORING_FEAT_SINGLE_MMAP`, or supplied by
+/// the application.
+pub struct RingMemory {
+    backing: Backing,
+}
+
+impl RingMemory {
+    /// Map the ring memory of a freshly set up ring
+    pub(crate) fn map(fd: RawFd, params: &io_uring_params) -> Result<Self, InitError> {
+        let sqe128 = params.flags.bits() & crate::IORING_SETUP_SQE128 != 0;
+        let cqe32 = params.flags.bits() & crate::IORING_SETUP_CQE32 != 0;
+
+        // Handle extended SQE (128 bytes instead of 64) and CQE (32 bytes
+        // instead of 16) formats
+        let sqe_entry_size = if sqe128 {
+            core::mem::size_of::<crate::io_uring_sqe128>()
+        } else {
+            core::mem::size_of::<io_uring_sqe>()
+        };
+        let cqe_entry_size = if cqe32 {
+            core::mem::size_of::<crate::io_uring_cqe32>()
+        } else {
+            core::mem::size_of::<crate::io_uring_cqe>()
+        };
+
+        let sq_ring_size = params.sq_off.array as usize
+            + (params.sq_entries as usize * core::mem::size_of::<u32>());
+        let cq_ring_size =
+            params.cq_off.cqes as usize + (params.cq_entries as usize * cqe_entry_size);
+        let sqe_size = params.sq_entries as usize * sqe_entry_size;
+
+        let sqes = RwMmap::new(fd, IORING_OFF_SQES, sqe_size, true)?;
+
+        let backing = if params.features.bits() & crate::IORING_FEAT_SINGLE_MMAP != 0 {
+            let size = sq_ring_size.max(cq_ring_size);
+            let rings = RwMmap::new(fd, IORING_OFF_SQ_RING, size, true)?;
+            Backing::Single { rings, sqes }
+        } else {
+            let sq = RwMmap::new(fd, IORING_OFF_SQ_RING, sq_ring_size, true)?;
+            let cq = RwMmap::new(fd, IORING_OFF_CQ_RING, cq_ring_size, true)?;
+            Backing::Separate { sq, cq, sqes }
+        };
+
+        Ok(Self { backing })
+    }
+
+    /// Wrap application-provided memory the ring was set up in
+    pub(crate) fn user(memory: UserRingMemory) -> Self {
+        Self {
+            backing: Backing::User(memory),
+        }
+    }
+
+    /// Start of the SQ ring
+    #[must_use]
+    pub fn sq_ring_ptr(&self) -> *mut u8 {
+        match &self.backing {
+            Backing::Separate { sq, .. } => sq.as_ptr().cast(),
+            Backing::Single { rings, .. } => rings.as_ptr().cast(),
+            Backing::User(memory) => memory.rings.as_ptr(),
+        }
+    }
+
+    /// Start of the CQ ring
+    #[must_use]
+    pub fn cq_ring_ptr(&self) -> *mut u8 {
+        match &self.backing {
+            Backing::Separate { cq, .. } => cq.as_ptr().cast(),
+            Backing::Single { rings, .. } => rings.as_ptr().cast(),
+            Backing::User(memory) => memory.rings.as_ptr(),
+        }
+    }
+
+    /// Start of the SQE array
+    #[must_use]
+    pub fn sqes_ptr(&self) -> *mut io_uring_sqe {
+        match &self.backing {
+            Backing::Separate { sqes, .. } | Backing::Single { sqes, .. } => sqes.as_ptr().cast(),
+            Backing::User(memory) => memory.sqes.as_ptr().cast(),
+        }
+    }
+
+    /// Whether the SQ and CQ rings share one mapping
+    #[must_use]
+    pub fn is_single_mmap(&self) -> bool {
+        matches!(self.backing, Backing::Single { .. })
+    }
+
+    /// Whether the memory was provided by the application
+    #[must_use]
+    pub fn is_user_provided(&self) -> bool {
+        matches!(self.backing, Backing::User(_))
+    }
+}
@@ -0,0 +1,195 @@
+//! Typed `io_uring` opcodes.
+
+use crate::err::UnknownOpcode;
+
+/// An `io_uring` operation, as stored in `io_uring_sqe::opcode`
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Opcode {
+    Nop = crate::IORING_OP_NOP,
+    Readv = crate::IORING_OP_READV,
+    Writev = crate::IORING_OP_WRITEV,
+    Fsync = crate::IORING_OP_FSYNC,
+    ReadFixed = crate::IORING_OP_READ_FIXED,
+    WriteFixed = crate::IORING_OP_WRITE_FIXED,
+    PollAdd = crate::IORING_OP_POLL_ADD,
+    PollRemove = crate::IORING_OP_POLL_REMOVE,
+    SyncFileRange = crate::IORING_OP_SYNC_FILE_RANGE,
+    Sendmsg = crate::IORING_OP_SENDMSG,
+    Recvmsg = crate::IORING_OP_RECVMSG,
+    Timeout = crate::IORING_OP_TIMEOUT,
+    TimeoutRemove = crate::IORING_OP_TIMEOUT_REMOVE,
+    Accept = crate::IORING_OP_ACCEPT,
+    AsyncCancel = crate::IORING_OP_ASYNC_CANCEL,
+    LinkTimeout = crate::IORING_OP_LINK_TIMEOUT,
+    Connect = crate::IORING_OP_CONNECT,
+    Fallocate = crate::IORING_OP_FALLOCATE,
+    Openat = crate::IORING_OP_OPENAT,
+    Close = crate::IORING_OP_CLOSE,
+    FilesUpdate = crate::IORING_OP_FILES_UPDATE,
+    Statx = crate::IORING_OP_STATX,
+    Read = crate::IORING_OP_READ,
+    Write = crate::IORING_OP_WRITE,
+    Fadvise = crate::IORING_OP_FADVISE,
+    Madvise = crate::IORING_OP_MADVISE,
+    Send = crate::IORING_OP_SEND,
+    Recv = crate::IORING_OP_RECV,
+    Openat2 = crate::IORING_OP_OPENAT2,
+    EpollCtl = crate::IORING_OP_EPOLL_CTL,
+    Splice = crate::IORING_OP_SPLICE,
+    ProvideBuffers = crate::IORING_OP_PROVIDE_BUFFERS,
+    RemoveBuffers = crate::IORING_OP_REMOVE_BUFFERS,
+    Tee = crate::IORING_OP_TEE,
+    Shutdown = crate::IORING_OP_SHUTDOWN,
+    Renameat = crate::IORING_OP_RENAMEAT,
+    Unlinkat = crate::IORING_OP_UNLINKAT,
+    Mkdirat = crate::IORING_OP_MKDIRAT,
+    Symlinkat = crate::IORING_OP_SYMLINKAT,
+    Linkat = crate::IORING_OP_LINKAT,
+    MsgRing = crate::IORING_OP_MSG_RING,
+    Fsetxattr = crate::IORING_OP_FSETXATTR,
+    Setxattr = crate::IORING_OP_SETXATTR,
+    Fgetxattr = crate::IORING_OP_FGETXATTR,
+    Getxattr = crate::IORING_OP_GETXATTR,
+    Socket = crate::IORING_OP_SOCKET,
+    UringCmd = crate::IORING_OP_URING_CMD,
+    SendZc = crate::IORING_OP_SEND_ZC,
+    SendmsgZc = crate::IORING_OP_SENDMSG_ZC,
+}
+
+impl Opcode {
+    /// Every opcode this crate knows, in numeric order
+    pub const ALL: [Self; 49] = [
+        Self::Nop,
+        Self::Readv,
+        Self::Writev,
+        Self::Fsync,
+        Self::ReadFixed,
+        Self::WriteFixed,
+        Self::PollAdd,
+        Self::PollRemove,
+        Self::SyncFileRange,
+        Self::Sendmsg,
+        Self::Recvmsg,
+        Self::Timeout,
+        Self::TimeoutRemove,
+        Self::Accept,
+        Self::AsyncCancel,
+        Self::LinkTimeout,
+        Self::Connect,
+        Self::Fallocate,
+        Self::Openat,
+        Self::Close,
+        Self::FilesUpdate,
+        Self::Statx,
+        Self::Read,
+        Self::Write,
+        Self::Fadvise,
+        Self::Madvise,
+        Self::Send,
+        Self::Recv,
+        Self::Openat2,
+        Self::EpollCtl,
+        Self::Splice,
+        Self::ProvideBuffers,
+        Self::RemoveBuffers,
+        Self::Tee,
+        Self::Shutdown,
+        Self::Renameat,
+        Self::Unlinkat,
+        Self::Mkdirat,
+        Self::Symlinkat,
+        Self::Linkat,
+        Self::MsgRing,
+        Self::Fsetxattr,
+        Self::Setxattr,
+        Self::Fgetxattr,
+        Self::Getxattr,
+        Self::Socket,
+        Self::UringCmd,
+        Self::SendZc,
+        Self::SendmsgZc,
+    ];
+
+    /// The kernel's name for the opcode, without the `IORING_OP_` prefix
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Nop => "NOP",
+            Self::Readv => "READV",
+            Self::Writev => "WRITEV",
+            Self::Fsync => "FSYNC",
+            Self::ReadFixed => "READ_FIXED",
+            Self::WriteFixed => "WRITE_FIXED",
+            Self::PollAdd => "POLL_ADD",
+            Self::PollRemove => "POLL_REMOVE",
+            Self::SyncFileRange => "SYNC_FILE_RANGE",
+            Self::Sendmsg => "SENDMSG",
+            Self::Recvmsg => "RECVMSG",
+            Self::Timeout => "TIMEOUT",
+            Self::TimeoutRemove => "TIMEOUT_REMOVE",
+            Self::Accept => "ACCEPT",
+            Self::AsyncCancel => "ASYNC_CANCEL",
+            Self::LinkTimeout => "LINK_TIMEOUT",
+            Self::Connect => "CONNECT",
+            Self::Fallocate => "FALLOCATE",
+            Self::Openat => "OPENAT",
+            Self::Close => "CLOSE",
+            Self::FilesUpdate => "FILES_UPDATE",
+            Self::Statx => "STATX",
+            Self::Read => "READ",
+            Self::Write => "WRITE",
+            Self::Fadvise => "FADVISE",
+            Self::Madvise => "MADVISE",
+            Self::Send => "SEND",
+            Self::Recv => "RECV",
+            Self::Openat2 => "OPENAT2",
+            Self::EpollCtl => "EPOLL_CTL",
+            Self::Splice => "SPLICE",
+            Self::ProvideBuffers => "PROVIDE_BUFFERS",
+            Self::RemoveBuffers => "REMOVE_BUFFERS",
+            Self::Tee => "TEE",
+            Self::Shutdown => "SHUTDOWN",
+            Self::Renameat => "RENAMEAT",
+            Self::Unlinkat => "UNLINKAT",
+            Self::Mkdirat => "MKDIRAT",
+            Self::Symlinkat => "SYMLINKAT",
+            Self::Linkat => "LINKAT",
+            Self::MsgRing => "MSG_RING",
+            Self::Fsetxattr => "FSETXATTR",
+            Self::Setxattr => "SETXATTR",
+            Self::Fgetxattr => "FGETXATTR",
+            Self::Getxattr => "GETXATTR",
+            Self::Socket => "SOCKET",
+            Self::UringCmd => "URING_CMD",
+            Self::SendZc => "SEND_ZC",
+            Self::SendmsgZc => "SENDMSG_ZC",
+        }
+    }
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = UnknownOpcode;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        // Opcodes are numbered contiguously from zero, so `ALL` is indexed
+        // by value.
+        Self::ALL
+            .get(usize::from(value))
+            .copied()
+            .ok_or(UnknownOpcode(value))
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> Self {
+        opcode as u8
+    }
+}
+
+impl core::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name())
+    }
+}
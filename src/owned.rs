@@ -0,0 +1,574 @@
+//! Operations that own their buffers until the kernel is done with them.
+//!
+//! The operations in [`sqe`](crate::sqe) borrow their buffers only while the
+//! SQE is prepared, yet the kernel reads and writes those buffers until the
+//! CQE arrives. The operations here take ownership instead: [`OwnedOps`]
+//! holds each one from [`push`](OwnedOps::push) until [`reap`](OwnedOps::reap)
+//! takes its CQE off the ring it was pushed to, and returns the buffers
+//! together with the result. Cancelling an operation doesn't return its
+//! buffers early; they come back with the operation's own CQE, usually
+//! carrying `-ECANCELED`.
+//!
+//! The [`Token`] of an operation is its entry's `user_data`. Another CQE
+//! carrying it, from an entry given the same `user_data` by hand or posted by
+//! `IORING_OP_MSG_RING`, would be taken for the operation's; don't reuse
+//! tokens elsewhere.
+//!
+//! An `OwnedOps` holds one operation type. To mix several, wrap them in an
+//! enum implementing [`OwnedOp`].
+
+use alloc::boxed::Box;
+use alloc::ffi::CString;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::mem::ManuallyDrop;
+
+use crate::cq::CompletionQueue;
+use crate::err::PushError;
+use crate::sq::SubmissionQueue;
+use crate::sqe::{Entry, Iovec};
+use crate::token::{OpSlab, Token};
+use crate::{io_uring_cqe, io_uring_sqe, PrepSqe, PrepSqeMut};
+
+/// A buffer whose contents stay put when the value itself is moved
+///
+/// # Safety
+/// The pointers returned must stay valid for `len()` bytes, and keep
+/// pointing at the same memory, for as long as the value is alive and not
+/// accessed through `&mut`, wherever it is moved.
+pub unsafe trait StableBuf {
+    fn stable_ptr(&self) -> *const u8;
+    fn stable_mut_ptr(&mut self) -> *mut u8;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// SAFETY: the bytes live on the heap and move with the `Vec`'s pointer only
+unsafe impl StableBuf for Vec<u8> {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn stable_mut_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+}
+
+// SAFETY: as for `Vec<u8>`
+unsafe impl StableBuf for Box<[u8]> {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn stable_mut_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+}
+
+/// An operation that owns everything the kernel will touch
+pub trait OwnedOp {
+    /// Resources handed back once the kernel is done with them
+    type Output;
+
+    /// Prepare the SQE from resources held by `self`
+    ///
+    /// `self` doesn't move between this call and the CQE, so the SQE may
+    /// point into `self` as well as into heap buffers it owns.
+    fn prep(&mut self, sqe: &mut io_uring_sqe);
+
+    fn into_output(self) -> Self::Output;
+}
+
+/// A reaped operation: its result and the resources it held
+#[derive(Debug)]
+pub struct Completion<T> {
+    pub token: Token,
+    /// The CQE's `res`: a byte count or similar on success, `-errno` on
+    /// failure
+    pub result: i32,
+    /// The CQE's `IORING_CQE_F_*` flags
+    pub flags: u32,
+    pub output: T,
+}
+
+/// A CQE taken off the CQ by [`OwnedOps::reap`]
+#[derive(Debug)]
+pub enum Reaped<T> {
+    /// The last CQE of an operation held there, handed back with its
+    /// resources
+    Done(Completion<T>),
+    /// Any other CQE, including those of held operations flagged
+    /// `IORING_CQE_F_MORE`
+    Other(io_uring_cqe),
+}
+
+/// Operations in flight, each owning its resources until its CQE is reaped
+pub struct OwnedOps<O> {
+    // Leaked rather than dropped while operations are in flight
+    ops: ManuallyDrop<OpSlab<O>>,
+    // Ring the operations in flight were pushed to
+    ring_id: usize,
+}
+
+impl<O: OwnedOp> OwnedOps<O> {
+    /// Room for up to `capacity` operations in flight
+    #[must_use]
+    pub fn with_capacity(capacity: u32) -> Self {
+        Self {
+            ops: ManuallyDrop::new(OpSlab::with_capacity(capacity)),
+            ring_id: 0,
+        }
+    }
+
+    /// Number of operations whose CQE hasn't been reaped
+    #[must_use]
+    pub fn in_flight(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Queue `op` and keep it until its CQE comes back
+    ///
+    /// The returned token is the entry's `user_data`.
+    ///
+    /// ## Errors
+    /// Returns `op` back if the SQ is full, if `capacity` operations are
+    /// already in flight, or if `sq` isn't an [`IoUring`](crate::IoUring)'s
+    /// or belongs to another ring than the operations in flight.
+    pub fn push(&mut self, sq: &mut SubmissionQueue, op: O) -> Result<Token, O> {
+        self.push_with_flags(sq, op, 0)
+    }
+
+    /// Queue `op` with `IOSQE_*` flags added to its entry
+    ///
+    /// ## Errors
+    /// As for [`push`](Self::push).
+    pub fn push_with_flags(
+        &mut self,
+        sq: &mut SubmissionQueue,
+        op: O,
+        flags: u8,
+    ) -> Result<Token, O> {
+        if sq.is_full() || sq.ring_id() == 0 {
+            return Err(op);
+        }
+        if self.ops.is_empty() {
+            self.ring_id = sq.ring_id();
+        } else if self.ring_id != sq.ring_id() {
+            return Err(op);
+        }
+        let token = self.ops.insert(0, op)?;
+
+        // Prepared only now that the op sits at its final address
+        let mut entry = Entry::default();
+        if let Some(op) = self.ops.get_mut(token) {
+            op.prep(&mut entry.0);
+        }
+        let entry = entry.user_data(token.user_data()).flags(flags);
+        match sq.push(&entry) {
+            Ok(()) => Ok(token),
+            Err(PushError) => match self.ops.remove(token) {
+                Some(op) => Err(op),
+                None => unreachable!("token was just issued"),
+            },
+        }
+    }
+
+    /// Take the next CQE off `cq`, handing back the operation it completes
+    ///
+    /// CQEs of other operations are returned as they are, as are all CQEs of
+    /// a `cq` other than the one of the ring the operations were pushed to.
+    /// Returns `None` if `cq` is empty.
+    pub fn reap(&mut self, cq: &mut CompletionQueue) -> Option<Reaped<O::Output>> {
+        if cq.is_empty() {
+            cq.update_kernel_tail();
+        }
+        let cqe = *cq.peek()?;
+        cq.advance(1);
+
+        if cq.ring_id() != self.ring_id || cqe.flags & crate::IORING_CQE_F_MORE != 0 {
+            return Some(Reaped::Other(cqe));
+        }
+        let token = Token::from_user_data(cqe.user_data);
+        let Some(op) = self.ops.remove(token) else {
+            return Some(Reaped::Other(cqe));
+        };
+        Some(Reaped::Done(Completion {
+            token,
+            result: cqe.res,
+            flags: cqe.flags,
+            output: op.into_output(),
+        }))
+    }
+
+    /// Ask the kernel to cancel the operation `token` names
+    ///
+    /// Its resources come back through [`reap`](Self::reap) once the kernel
+    /// posts its CQE. The cancel request posts a CQE of its own
+    /// with `user_data` 0. Returns `false`, queuing nothing, if the operation
+    /// isn't in flight.
+    ///
+    /// ## Errors
+    /// Returns `PushError` if the SQ is full.
+    pub fn cancel(&mut self, sq: &mut SubmissionQueue, token: Token) -> Result<bool, PushError> {
+        if !self.ops.contains(token) {
+            return Ok(false);
+        }
+
+        sq.push(&crate::sqe::AsyncCancel::new(token.user_data(), 0).build())?;
+        Ok(true)
+    }
+}
+
+impl<O> Drop for OwnedOps<O> {
+    fn drop(&mut self) {
+        // The kernel may still use the resources of operations in flight, so
+        // those are leaked instead of freed.
+        if self.ops.is_empty() {
+            // SAFETY: `ops` isn't used again
+            unsafe { ManuallyDrop::drop(&mut self.ops) };
+        }
+    }
+}
+
+/// Read into an owned buffer, up to its length
+pub struct Read<B> {
+    fd: i32,
+    buf: B,
+    offset: u64,
+}
+
+impl<B: StableBuf> Read<B> {
+    #[must_use]
+    pub fn new(fd: i32, buf: B, offset: u64) -> Self {
+        Self { fd, buf, offset }
+    }
+}
+
+impl<B: StableBuf> OwnedOp for Read<B> {
+    type Output = B;
+
+    fn prep(&mut self, sqe: &mut io_uring_sqe) {
+        // SAFETY: `StableBuf` guarantees the pointer is valid for `len` bytes
+        let buf =
+            unsafe { core::slice::from_raw_parts_mut(self.buf.stable_mut_ptr(), self.buf.len()) };
+        crate::sqe::Read::new(self.fd, buf, self.offset).prep(sqe);
+    }
+
+    fn into_output(self) -> B {
+        self.buf
+    }
+}
+
+/// Write all of an owned buffer
+pub struct Write<B> {
+    fd: i32,
+    buf: B,
+    offset: u64,
+}
+
+impl<B: StableBuf> Write<B> {
+    #[must_use]
+    pub fn new(fd: i32, buf: B, offset: u64) -> Self {
+        Self { fd, buf, offset }
+    }
+}
+
+impl<B: StableBuf> OwnedOp for Write<B> {
+    type Output = B;
+
+    fn prep(&mut self, sqe: &mut io_uring_sqe) {
+        // SAFETY: as for `Read`
+        let buf = unsafe { core::slice::from_raw_parts(self.buf.stable_ptr(), self.buf.len()) };
+        crate::sqe::Write::new(self.fd, buf, self.offset).prep(sqe);
+    }
+
+    fn into_output(self) -> B {
+        self.buf
+    }
+}
+
+/// Read into a leased registered buffer, up to its length
+pub struct ReadFixed {
+    fd: i32,
+    buf: crate::FixedBuf,
+    offset: u64,
+}
+
+impl ReadFixed {
+    #[must_use]
+    pub fn new(fd: i32, buf: crate::FixedBuf, offset: u64) -> Self {
+        Self { fd, buf, offset }
+    }
+}
+
+impl OwnedOp for ReadFixed {
+    type Output = crate::FixedBuf;
+
+    fn prep(&mut self, sqe: &mut io_uring_sqe) {
+        let index = self.buf.index();
+        crate::sqe::ReadFixed::new(self.fd, &mut self.buf, self.offset, index).prep(sqe);
+    }
+
+    fn into_output(self) -> crate::FixedBuf {
+        self.buf
+    }
+}
+
+/// Write all of a leased registered buffer
+pub struct WriteFixed {
+    fd: i32,
+    buf: crate::FixedBuf,
+    offset: u64,
+}
+
+impl WriteFixed {
+    #[must_use]
+    pub fn new(fd: i32, buf: crate::FixedBuf, offset: u64) -> Self {
+        Self { fd, buf, offset }
+    }
+}
+
+impl OwnedOp for WriteFixed {
+    type Output = crate::FixedBuf;
+
+    fn prep(&mut self, sqe: &mut io_uring_sqe) {
+        crate::sqe::WriteFixed::new(self.fd, &self.buf, self.offset, self.buf.index()).prep(sqe);
+    }
+
+    fn into_output(self) -> crate::FixedBuf {
+        self.buf
+    }
+}
+
+/// Open a file by an owned path
+pub struct OpenAt {
+    dirfd: i32,
+    path: CString,
+    flags: u32,
+    mode: u32,
+}
+
+impl OpenAt {
+    #[must_use]
+    pub fn new(dirfd: i32, path: CString, flags: u32, mode: u32) -> Self {
+        Self {
+            dirfd,
+            path,
+            flags,
+            mode,
+        }
+    }
+}
+
+impl OwnedOp for OpenAt {
+    type Output = CString;
+
+    fn prep(&mut self, sqe: &mut io_uring_sqe) {
+        crate::sqe::OpenAt::new(self.dirfd, &self.path, self.flags, self.mode).prep(sqe);
+    }
+
+    fn into_output(self) -> CString {
+        self.path
+    }
+}
+
+/// A timeout holding its own `Timespec`
+pub struct Timeout {
+    ts: crate::Timespec,
+    count: u32,
+    flags: u32,
+}
+
+impl Timeout {
+    #[must_use]
+    pub fn new(ts: crate::Timespec, count: u32, flags: u32) -> Self {
+        Self { ts, count, flags }
+    }
+
+    #[must_use]
+    pub fn relative(ts: crate::Timespec) -> Self {
+        Self::new(ts, 0, 0)
+    }
+
+    #[must_use]
+    pub fn absolute(ts: crate::Timespec) -> Self {
+        Self::new(ts, 0, crate::IORING_TIMEOUT_ABS)
+    }
+}
+
+impl OwnedOp for Timeout {
+    type Output = crate::Timespec;
+
+    fn prep(&mut self, sqe: &mut io_uring_sqe) {
+        crate::sqe::Timeout::new(&self.ts, self.count, self.flags).prep(sqe);
+    }
+
+    fn into_output(self) -> crate::Timespec {
+        self.ts
+    }
+}
+
+/// An owned message for `sendmsg`/`recvmsg`: peer address, data buffers and
+/// control data
+#[derive(Default)]
+pub struct MsgHdr {
+    name: Vec<u8>,
+    bufs: Vec<Vec<u8>>,
+    control: Vec<u8>,
+    iovecs: Vec<Iovec>,
+    // Points into the fields above once prepared; updated by the kernel on
+    // receive
+    hdr: Option<crate::sqe::MsgHdr<'static>>,
+}
+
+impl MsgHdr {
+    /// A message made of `bufs`, scattered or gathered in order
+    #[must_use]
+    pub fn new(bufs: Vec<Vec<u8>>) -> Self {
+        Self {
+            bufs,
+            ..Self::default()
+        }
+    }
+
+    /// Set the peer address, or the space to receive it into
+    #[must_use]
+    pub fn with_name(mut self, name: Vec<u8>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Set the control data, or the space to receive it into
+    #[must_use]
+    pub fn with_control(mut self, control: Vec<u8>) -> Self {
+        self.control = control;
+        self
+    }
+
+    #[must_use]
+    pub fn bufs(&self) -> &[Vec<u8>] {
+        &self.bufs
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &[u8] {
+        let len = self
+            .hdr
+            .as_ref()
+            .map_or(self.name.len(), |hdr| hdr.msg_namelen as usize);
+        &self.name[..len.min(self.name.len())]
+    }
+
+    #[must_use]
+    pub fn control(&self) -> &[u8] {
+        let len = self
+            .hdr
+            .as_ref()
+            .map_or(self.control.len(), |hdr| hdr.msg_controllen);
+        &self.control[..len.min(self.control.len())]
+    }
+
+    /// `MSG_*` flags the kernel reported on receive
+    #[must_use]
+    pub fn flags(&self) -> i32 {
+        self.hdr.as_ref().map_or(0, |hdr| hdr.msg_flags)
+    }
+
+    /// Take back the data buffers
+    #[must_use]
+    pub fn into_bufs(self) -> Vec<Vec<u8>> {
+        self.bufs
+    }
+
+    fn prepare(&mut self) -> *mut crate::sqe::MsgHdr<'static> {
+        self.iovecs = self
+            .bufs
+            .iter_mut()
+            .map(|buf| Iovec::new(buf.as_mut_ptr().cast::<c_void>(), buf.len()))
+            .collect();
+        // SAFETY: the iovecs live on the heap, untouched until `self` is
+        // dropped, and the header never escapes `self`
+        let iov =
+            unsafe { core::slice::from_raw_parts_mut(self.iovecs.as_mut_ptr(), self.iovecs.len()) };
+
+        let mut hdr = crate::sqe::MsgHdr::with_iov(iov);
+        if !self.name.is_empty() {
+            hdr.msg_name = self.name.as_mut_ptr().cast::<c_void>();
+            hdr.msg_namelen = self.name.len() as u32;
+        }
+        if !self.control.is_empty() {
+            hdr.set_control(
+                self.control.as_mut_ptr().cast::<c_void>(),
+                self.control.len() as u32,
+            );
+        }
+        self.hdr.insert(hdr)
+    }
+}
+
+/// Send an owned message
+pub struct SendMsg {
+    fd: i32,
+    msg: MsgHdr,
+    flags: i32,
+}
+
+impl SendMsg {
+    #[must_use]
+    pub fn new(fd: i32, msg: MsgHdr, flags: i32) -> Self {
+        Self { fd, msg, flags }
+    }
+}
+
+impl OwnedOp for SendMsg {
+    type Output = MsgHdr;
+
+    fn prep(&mut self, sqe: &mut io_uring_sqe) {
+        let hdr = self.msg.prepare();
+        // SAFETY: `prepare` returns a pointer into `self.msg`
+        crate::sqe::SendMsg::new(self.fd, unsafe { &*hdr }, self.flags).prep(sqe);
+    }
+
+    fn into_output(self) -> MsgHdr {
+        self.msg
+    }
+}
+
+/// Receive into an owned message
+pub struct RecvMsg {
+    fd: i32,
+    msg: MsgHdr,
+    flags: i32,
+}
+
+impl RecvMsg {
+    #[must_use]
+    pub fn new(fd: i32, msg: MsgHdr, flags: i32) -> Self {
+        Self { fd, msg, flags }
+    }
+}
+
+impl OwnedOp for RecvMsg {
+    type Output = MsgHdr;
+
+    fn prep(&mut self, sqe: &mut io_uring_sqe) {
+        let hdr = self.msg.prepare();
+        // SAFETY: as for `SendMsg`
+        crate::sqe::RecvMsg::new(self.fd, unsafe { &mut *hdr }, self.flags).prep(sqe);
+    }
+
+    fn into_output(self) -> MsgHdr {
+        self.msg
+    }
+}
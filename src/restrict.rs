@@ -0,0 +1,104 @@
+//! Restrictions for rings handed to less trusted code.
+//!
+//! A ring created with [`SetupBuilder::disabled`](crate::SetupBuilder::disabled)
+//! accepts no submissions until it is enabled. Before that, a
+//! [`RestrictionSet`] can be registered on it, once: from then on only the
+//! listed `io_uring_register` operations and SQE opcodes are accepted, and
+//! every SQE's flags must include the required ones and stay within the
+//! allowed ones. Anything else fails with `-EACCES`. The restrictions can't
+//! be lifted for the lifetime of the ring.
+
+use alloc::vec::Vec;
+
+use rustix::io_uring::IoringRegisterOp;
+
+use crate::err::InitError;
+use crate::io_uring::IoUring;
+use crate::opcode::Opcode;
+use crate::Restriction;
+
+/// Builder for a ring's allowlist
+///
+/// An empty set allows no opcode and no register operation.
+#[derive(Debug, Clone, Default)]
+pub struct RestrictionSet {
+    restrictions: Vec<Restriction>,
+}
+
+impl RestrictionSet {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with(mut self, opcode: u16, arg: u8) -> Self {
+        self.restrictions.push(Restriction {
+            opcode,
+            arg,
+            ..Default::default()
+        });
+        self
+    }
+
+    // The kernel keeps only the last entry of each flag kind, so merge them
+    fn with_flags(mut self, opcode: u16, flags: u8) -> Self {
+        match self.restrictions.iter_mut().find(|r| r.opcode == opcode) {
+            Some(restriction) => {
+                restriction.arg |= flags;
+                self
+            }
+            None => self.with(opcode, flags),
+        }
+    }
+
+    /// Allow an `io_uring_register` operation
+    #[must_use]
+    pub fn allow_register_op(self, op: IoringRegisterOp) -> Self {
+        self.with(crate::IORING_RESTRICTION_REGISTER_OP, op as u8)
+    }
+
+    /// Allow SQEs with this opcode
+    #[must_use]
+    pub fn allow_op(self, op: Opcode) -> Self {
+        self.with(crate::IORING_RESTRICTION_SQE_OP, op.into())
+    }
+
+    /// Allow these `IOSQE_*` flags, on top of the required ones
+    ///
+    /// Without this, SQEs may carry only the required flags.
+    #[must_use]
+    pub fn allow_sqe_flags(self, flags: u8) -> Self {
+        self.with_flags(crate::IORING_RESTRICTION_SQE_FLAGS_ALLOWED, flags)
+    }
+
+    /// Require these `IOSQE_*` flags on every SQE, e.g. `IOSQE_FIXED_FILE`
+    /// to confine the ring to its registered files
+    #[must_use]
+    pub fn require_sqe_flags(self, flags: u8) -> Self {
+        self.with_flags(crate::IORING_RESTRICTION_SQE_FLAGS_REQUIRED, flags)
+    }
+
+    /// The entries passed to `IORING_REGISTER_RESTRICTIONS`
+    #[must_use]
+    pub fn restrictions(&self) -> &[Restriction] {
+        &self.restrictions
+    }
+
+    /// Register the restrictions on a disabled ring, without enabling it
+    ///
+    /// ## Errors
+    /// As for [`Submitter::register_restrictions`](crate::Submitter::register_restrictions).
+    pub fn register(&self, ring: &IoUring) -> Result<(), InitError> {
+        ring.register_restrictions(&self.restrictions)
+    }
+
+    /// Register the restrictions on a disabled ring, then enable it
+    ///
+    /// ## Errors
+    /// As for [`register`](Self::register), or `InitError::RegisterFailed`
+    /// if enabling the ring fails.
+    pub fn apply(&self, ring: &IoUring) -> Result<(), InitError> {
+        self.register(ring)?;
+        ring.enable_rings()
+    }
+}
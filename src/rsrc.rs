@@ -0,0 +1,78 @@
+//! Tags for registered buffers and files.
+//!
+//! Buffers and files registered with a tag (Linux 5.13+,
+//! `IORING_FEAT_RSRC_TAGS`) make the kernel post a CQE carrying the tag as
+//! its `user_data` once it has truly let go of the resource: after the
+//! resource was replaced or unregistered, and every request still using it
+//! has completed. Until then the memory behind a registered buffer must not
+//! be reused, nor a slot's file assumed closed.
+//!
+//! Those CQEs have no flag setting them apart, so an [`RsrcTag`] lives in a
+//! reserved part of the `user_data` space: the top byte is all ones. Keep
+//! other `user_data` values, e.g. [`Token`](crate::Token) kinds, out of it,
+//! and [`RsrcEvent::from_cqe`] picks the tagged CQEs out.
+
+use crate::io_uring_cqe;
+
+// Top byte of every tag
+const MARK: u64 = 0xff << 56;
+// Set for files, clear for buffers
+const FILE_BIT: u64 = 1 << 32;
+
+/// Tag of a registered buffer or file
+///
+/// Packs a caller-chosen `u32`, typically the slot or what occupies it, with
+/// the kind of resource.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RsrcTag(u64);
+
+impl RsrcTag {
+    /// No tag: the kernel posts nothing when the resource goes
+    pub const NONE: Self = Self(0);
+
+    /// Tag for a registered buffer
+    #[must_use]
+    pub const fn buffer(value: u32) -> Self {
+        Self(MARK | value as u64)
+    }
+
+    /// Tag for a registered file
+    #[must_use]
+    pub const fn file(value: u32) -> Self {
+        Self(MARK | FILE_BIT | value as u64)
+    }
+
+    /// The `user_data` of the CQE posted for this tag
+    #[must_use]
+    pub const fn user_data(self) -> u64 {
+        self.0
+    }
+}
+
+/// A registered resource the kernel is done with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RsrcEvent {
+    /// The buffer tagged [`RsrcTag::buffer`] with this value was released;
+    /// its memory can be reused
+    Buffer(u32),
+    /// The file tagged [`RsrcTag::file`] with this value was released
+    File(u32),
+}
+
+impl RsrcEvent {
+    /// The event `cqe` reports, or `None` if it isn't a resource tag CQE
+    #[must_use]
+    pub fn from_cqe(cqe: &io_uring_cqe) -> Option<Self> {
+        let tag = cqe.user_data;
+        if tag & MARK != MARK || tag & !(MARK | FILE_BIT | u64::from(u32::MAX)) != 0 {
+            return None;
+        }
+        let value = tag as u32;
+        Some(if tag & FILE_BIT == 0 {
+            Self::Buffer(value)
+        } else {
+            Self::File(value)
+        })
+    }
+}
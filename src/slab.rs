@@ -0,0 +1,129 @@
+//! Fixed-capacity index free list.
+//!
+//! Backs the SQE cache and the `user_data` allocator. The link array is
+//! allocated once, sized from the ring entries, so pushing and popping never
+//! touch the heap. Each index is either on the list, in which case its link
+//! names the next free index, or off it and marked [`UNLISTED`]; that makes
+//! pushing an index twice a cheap no-op instead of a corrupted list.
+
+use core::cell::Cell;
+
+use alloc::boxed::Box;
+
+// Link value of an index that isn't on the list
+const UNLISTED: u32 = u32::MAX;
+// Link value of the last index on the list, and the head of an empty list
+const END: u32 = u32::MAX - 1;
+
+pub(crate) struct IndexList {
+    links: Box<[Cell<u32>]>,
+    head: Cell<u32>,
+    len: Cell<u32>,
+}
+
+impl IndexList {
+    /// An empty list able to hold the indices `0..capacity`
+    pub(crate) fn new(capacity: u32) -> Self {
+        // Keep the two sentinels out of the index space
+        let capacity = capacity.min(END);
+        Self {
+            links: (0..capacity).map(|_| Cell::new(UNLISTED)).collect(),
+            head: Cell::new(END),
+            len: Cell::new(0),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> u32 {
+        self.links.len() as u32
+    }
+
+    pub(crate) fn len(&self) -> u32 {
+        self.len.get()
+    }
+
+    /// Put `index` on the list
+    ///
+    /// Returns `false`, leaving the list untouched, if `index` is out of
+    /// range or already listed.
+    pub(crate) fn push(&self, index: u32) -> bool {
+        let Some(link) = self.links.get(index as usize) else {
+            return false;
+        };
+        if link.get() != UNLISTED {
+            return false;
+        }
+
+        link.set(self.head.get());
+        self.head.set(index);
+        self.len.set(self.len.get() + 1);
+        true
+    }
+
+    /// Take the most recently pushed index off the list
+    pub(crate) fn pop(&self) -> Option<u32> {
+        let index = self.head.get();
+        if index == END {
+            return None;
+        }
+
+        let link = &self.links[index as usize];
+        self.head.set(link.get());
+        link.set(UNLISTED);
+        self.len.set(self.len.get() - 1);
+        Some(index)
+    }
+
+    pub(crate) fn clear(&self) {
+        while self.pop().is_some() {}
+    }
+}
+
+/// `user_data` values `1..=capacity`, freed values being handed out again
+/// before any fresh one
+///
+/// Zero is never handed out, so it stays free for untracked operations.
+pub(crate) struct UserDataSlab {
+    freed: IndexList,
+    // Indices at or above this have never been handed out
+    fresh: Cell<u32>,
+}
+
+impl UserDataSlab {
+    pub(crate) fn new(capacity: u32) -> Self {
+        Self {
+            freed: IndexList::new(capacity),
+            fresh: Cell::new(0),
+        }
+    }
+
+    pub(crate) fn alloc(&self) -> Option<u64> {
+        let index = self.freed.pop().or_else(|| {
+            let index = self.fresh.get();
+            (index < self.freed.capacity()).then(|| {
+                self.fresh.set(index + 1);
+                index
+            })
+        })?;
+        Some(u64::from(index) + 1)
+    }
+
+    /// Returns `false` if `user_data` isn't currently allocated
+    pub(crate) fn free(&self, user_data: u64) -> bool {
+        match user_data.checked_sub(1).map(u32::try_from) {
+            Some(Ok(index)) if index < self.fresh.get() => self.freed.push(index),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> u32 {
+        self.freed.capacity()
+    }
+
+    pub(crate) fn allocated(&self) -> u32 {
+        self.fresh.get() - self.freed.len()
+    }
+
+    pub(crate) fn available(&self) -> u32 {
+        self.freed.len()
+    }
+}
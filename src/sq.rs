@@ -1,8 +1,24 @@
+//! Submission queue ring.
+//!
+//! Every raw pointer held by [`SubmissionQueue`] points into memory mapped
+//! from the ring fd and owned by the `IoUring` the queue belongs to. The
+//! queue never outlives that mapping: it is only handed out by reference,
+//! through `IoUring::submission` and `IoUring::split`. Fields shared with the
+//! kernel (`khead`, `ktail`, `kflags`, `kdropped`) are only touched
+//! atomically; the SQE array and index array are only written for slots
+//! between the kernel head and our local tail, which the kernel does not read
+//! until the tail is published.
+
 use core::sync::atomic::{AtomicU32, Ordering};
 
 use rustix::io_uring::io_sqring_offsets;
 
-use crate::io_uring_sqe;
+#[cfg(feature = "alloc")]
+use crate::slab::IndexList;
+
+use crate::err::PushError;
+use crate::sqe::Entry;
+use crate::{io_uring_sqe, io_uring_sqe128};
 
 pub struct SubmissionQueue {
     khead: *const AtomicU32,
@@ -15,19 +31,39 @@ pub struct SubmissionQueue {
     sqe_ptr: *mut io_uring_sqe,
     sqe_mask: u32,
     sqe_entries: u32,
+    // log2 of the SQE size in units of `io_uring_sqe` (1 with SQE128)
+    sqe_shift: u32,
     head: AtomicU32,
     tail: AtomicU32,
-    // SQE cache for performance optimization
-    sqe_cache: core::cell::RefCell<Vec<*mut io_uring_sqe>>,
+    // Published entries still to post their last CQE, shared with the CQ,
+    // and the CQ capacity, when in-flight entries are capped at the CQ size
+    inflight_limit: Option<(*const AtomicU32, u32)>,
+    // Number of the `IoUring` the queue belongs to, 0 for queues made by hand
+    ring_id: usize,
+    // Slots of reclaimed SQEs, preallocated for every slot in the array
+    #[cfg(feature = "alloc")]
+    sqe_cache: IndexList,
 }
 
+// SAFETY: the queue exclusively owns its view of the SQ ring (see the module
+// docs), so moving it to another thread is sound. It is not `Sync`; shared
+// access from several producers would race on the local tail.
+unsafe impl Send for SubmissionQueue {}
+
 impl SubmissionQueue {
+    /// Create a view of an SQ ring
+    ///
+    /// # Safety
+    /// `sq_ptr` must point to the mapped SQ ring described by `offsets`, and
+    /// `sqe_ptr` to the mapped SQE array of `sq_entries` entries, each 128
+    /// bytes long if `sqe128` is set. Both mappings must outlive the queue.
     #[must_use]
     pub unsafe fn new(
         sq_ptr: *mut u8,
         offsets: &io_sqring_offsets,
         sqe_ptr: *mut io_uring_sqe,
         sq_entries: u32,
+        sqe128: bool,
     ) -> Self {
         let khead = unsafe { sq_ptr.add(offsets.head as usize) as *const AtomicU32 };
         let ktail = unsafe { sq_ptr.add(offsets.tail as usize) as *mut AtomicU32 };
@@ -35,8 +71,10 @@ impl SubmissionQueue {
         let kdropped = unsafe { sq_ptr.add(offsets.dropped as usize) as *const AtomicU32 };
         let array = unsafe { sq_ptr.add(offsets.array as usize) as *mut u32 };
 
-        let kring_mask = offsets.ring_mask;
-        let kring_entries = offsets.ring_entries;
+        // The offsets locate the mask and entry count in the ring; the values
+        // themselves are written there by the kernel.
+        let kring_mask = unsafe { *sq_ptr.add(offsets.ring_mask as usize).cast::<u32>() };
+        let kring_entries = unsafe { *sq_ptr.add(offsets.ring_entries as usize).cast::<u32>() };
 
         Self {
             khead,
@@ -49,9 +87,13 @@ impl SubmissionQueue {
             sqe_ptr,
             sqe_mask: sq_entries - 1,
             sqe_entries: sq_entries,
+            sqe_shift: u32::from(sqe128),
             head: AtomicU32::new(0),
             tail: AtomicU32::new(0),
-            sqe_cache: core::cell::RefCell::new(Vec::new()),
+            inflight_limit: None,
+            ring_id: 0,
+            #[cfg(feature = "alloc")]
+            sqe_cache: IndexList::new(sq_entries),
         }
     }
 
@@ -65,6 +107,66 @@ impl SubmissionQueue {
         self.kring_entries
     }
 
+    /// Cap entries queued or awaiting completion at `limit`
+    ///
+    /// `outstanding` counts published entries until the CQ reaps their last
+    /// CQE; it must outlive the queue.
+    pub(crate) fn set_inflight_limit(&mut self, outstanding: *const AtomicU32, limit: u32) {
+        self.inflight_limit = Some((outstanding, limit));
+    }
+
+    pub(crate) fn set_ring_id(&mut self, ring_id: usize) {
+        self.ring_id = ring_id;
+    }
+
+    /// Number of the `IoUring` the queue belongs to, shared with its CQ, or
+    /// 0 if the queue was made with [`new`](Self::new)
+    pub(crate) fn ring_id(&self) -> usize {
+        self.ring_id
+    }
+
+    /// Entries queued or awaiting their last CQE, if tracked
+    pub(crate) fn inflight(&self) -> Option<u32> {
+        let (outstanding, _) = self.inflight_limit?;
+        let queued = self
+            .tail
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.get_ktail());
+        // SAFETY: see `set_inflight_limit`
+        Some(queued.saturating_add(unsafe { (*outstanding).load(Ordering::Acquire) }))
+    }
+
+    /// Add the entries about to be published that will post a CQE to
+    /// `outstanding`
+    fn count_published(&self, outstanding: *const AtomicU32, tail: u32) {
+        let mut pos = self.get_ktail();
+        let mut expected = 0;
+        while pos != tail {
+            // SAFETY: the slots between the kernel tail and ours are ours,
+            // and the array maps each of them to a filled SQE
+            let skip = unsafe {
+                let index = *self.array.add((pos & self.kring_mask) as usize);
+                (*self.sqe_at(index)).flags & crate::IOSQE_CQE_SKIP_SUCCESS != 0
+            };
+            expected += u32::from(!skip);
+            pos = pos.wrapping_add(1);
+        }
+        // SAFETY: see `set_inflight_limit`
+        unsafe { (*outstanding).fetch_add(expected, Ordering::AcqRel) };
+    }
+
+    pub(crate) fn khead_ptr(&self) -> *const AtomicU32 {
+        self.khead
+    }
+
+    pub(crate) fn ktail_ptr(&self) -> *const AtomicU32 {
+        self.ktail
+    }
+
+    pub(crate) fn kflags_ptr(&self) -> *const AtomicU32 {
+        self.kflags
+    }
+
     fn get_khead(&self) -> u32 {
         unsafe { (*self.khead).load(Ordering::Acquire) }
     }
@@ -81,18 +183,38 @@ impl SubmissionQueue {
     pub fn space_left(&self) -> u32 {
         let tail = self.tail.load(Ordering::Acquire);
         let head = self.head.load(Ordering::Relaxed);
-        self.kring_entries - tail.wrapping_sub(head)
+        let space = self.kring_entries - tail.wrapping_sub(head);
+
+        match (self.inflight_limit, self.inflight()) {
+            (Some((_, limit)), Some(inflight)) => space.min(limit.saturating_sub(inflight)),
+            _ => space,
+        }
     }
 
+    /// Whether the kernel holds CQEs that didn't fit in the CQ ring
     #[must_use]
-    pub fn peek_sqe(&mut self) -> Option<&mut io_uring_sqe> {
-        let tail = self.tail.load(Ordering::Acquire);
-        let head = self.head.load(Ordering::Relaxed);
+    pub fn cq_overflow(&self) -> bool {
+        unsafe { (*self.kflags).load(Ordering::Acquire) & crate::IORING_SQ_CQ_OVERFLOW != 0 }
+    }
 
-        if tail == head.wrapping_add(self.kring_entries) {
+    /// Whether the ring uses 128-byte SQEs
+    #[must_use]
+    pub fn is_sqe128(&self) -> bool {
+        self.sqe_shift != 0
+    }
+
+    /// Pointer to the SQE in slot `index`, accounting for the entry size
+    fn sqe_at(&self, index: u32) -> *mut io_uring_sqe {
+        unsafe { self.sqe_ptr.add((index as usize) << self.sqe_shift) }
+    }
+
+    fn next_sqe(&mut self) -> Option<*mut io_uring_sqe> {
+        if self.is_full() {
             return None;
         }
 
+        let tail = self.tail.load(Ordering::Acquire);
+
         let index = tail & self.kring_mask;
 
         // Tell the kernel which SQE entry we filled.
@@ -102,8 +224,27 @@ impl SubmissionQueue {
 
         self.tail.store(tail.wrapping_add(1), Ordering::Release);
 
-        let sqe = unsafe { &mut *self.sqe_ptr.add(index as usize) };
-        Some(sqe)
+        Some(self.sqe_at(index))
+    }
+
+    #[must_use]
+    pub fn peek_sqe(&mut self) -> Option<&mut io_uring_sqe> {
+        let sqe = self.next_sqe()?;
+        Some(unsafe { &mut *sqe })
+    }
+
+    /// Get the next free 128-byte SQE
+    ///
+    /// Returns `None` if the queue is full or the ring was not set up with
+    /// `IORING_SETUP_SQE128`.
+    #[must_use]
+    pub fn peek_sqe128(&mut self) -> Option<&mut io_uring_sqe128> {
+        if !self.is_sqe128() {
+            return None;
+        }
+
+        let sqe = self.next_sqe()?;
+        Some(unsafe { &mut *sqe.cast::<io_uring_sqe128>() })
     }
 
     pub fn advance(&mut self, count: u32) {
@@ -126,22 +267,12 @@ impl SubmissionQueue {
         let index = tail & self.kring_mask;
         let array_index = tail & self.kring_mask;
 
+        let target = self.sqe_at(index);
         unsafe {
-            let target = self.sqe_ptr.add(index as usize);
-            (*target).opcode = sqe.opcode;
-            (*target).flags = sqe.flags;
-            (*target).ioprio = sqe.ioprio;
-            (*target).fd = sqe.fd;
-            (*target).off = sqe.off;
-            (*target).addr = sqe.addr;
-            (*target).len = sqe.len;
-            (*target).rw_flags = sqe.rw_flags;
-            (*target).user_data = sqe.user_data;
-            (*target).buf_index = sqe.buf_index;
-            (*target).personality = sqe.personality;
-            (*target).splice_fd_in = sqe.splice_fd_in;
-            (*target).addr3 = sqe.addr3;
-            (*target).__pad2 = sqe.__pad2;
+            if self.is_sqe128() {
+                *target.cast::<io_uring_sqe128>() = io_uring_sqe128::default();
+            }
+            *target = *sqe;
         }
         unsafe {
             core::ptr::write_volatile(self.array.add(array_index as usize), index);
@@ -151,10 +282,57 @@ impl SubmissionQueue {
         self.tail.store(new_tail, Ordering::Release);
     }
 
+    /// Whether the in-flight cap, if any, can track `sqe`
+    ///
+    /// A failed `IOSQE_CQE_SKIP_SUCCESS` entry still posts a CQE, so whether
+    /// one counts is only known once it completed.
+    fn is_trackable(&self, sqe: &io_uring_sqe) -> bool {
+        self.inflight_limit.is_none() || sqe.flags & crate::IOSQE_CQE_SKIP_SUCCESS == 0
+    }
+
+    /// Copy a prepared entry into the queue
+    ///
+    /// ## Errors
+    /// Returns `PushError` if the queue is full, or if in-flight entries are
+    /// capped and the entry has `IOSQE_CQE_SKIP_SUCCESS`.
+    pub fn push(&mut self, entry: &Entry) -> Result<(), PushError> {
+        if self.is_full() || !self.is_trackable(&entry.0) {
+            return Err(PushError);
+        }
+
+        self.write_sqe(&entry.0);
+        Ok(())
+    }
+
+    /// Copy several prepared entries into the queue
+    ///
+    /// Either all entries are queued or, if there isn't room for all of
+    /// them, none are.
+    ///
+    /// ## Errors
+    /// Returns `PushError` if the queue lacks space for every entry, or as
+    /// for [`push`](Self::push).
+    pub fn push_multiple(&mut self, entries: &[Entry]) -> Result<(), PushError> {
+        if (self.space_left() as usize) < entries.len()
+            || !entries.iter().all(|entry| self.is_trackable(&entry.0))
+        {
+            return Err(PushError);
+        }
+
+        for entry in entries {
+            self.write_sqe(&entry.0);
+        }
+        Ok(())
+    }
+
     pub fn update_kernel_tail(&self) -> u32 {
         let tail = self.tail.load(Ordering::Acquire);
         let head = self.head.load(Ordering::Relaxed);
         let to_submit = tail.wrapping_sub(head);
+        // Counted before the kernel can see, and complete, them
+        if let Some((outstanding, _)) = self.inflight_limit {
+            self.count_published(outstanding, tail);
+        }
         self.set_ktail(tail);
         to_submit
     }
@@ -164,33 +342,62 @@ impl SubmissionQueue {
         self.head.store(khead, Ordering::Release);
     }
 
+    /// Publish queued entries to the kernel and pick up its consumed head
+    ///
+    /// Returns the number of entries published but not yet consumed. Call
+    /// this before [`Submitter::submit`](crate::Submitter::submit) when the
+    /// ring has been split.
+    pub fn sync(&mut self) -> u32 {
+        let to_submit = self.update_kernel_tail();
+        self.update_from_kernel();
+        to_submit
+    }
+
+    /// Slot of an SQE in the array, if `sqe_ptr` points at the start of one
+    fn sqe_index(&self, sqe_ptr: *mut io_uring_sqe) -> Option<u32> {
+        let offset = (sqe_ptr as usize).checked_sub(self.sqe_ptr as usize)?;
+        // Entries are 64 or 128 bytes, so shifts stand in for division
+        let shift = core::mem::size_of::<io_uring_sqe>().trailing_zeros() + self.sqe_shift;
+        if offset & ((1 << shift) - 1) != 0 {
+            return None;
+        }
+        u32::try_from(offset >> shift)
+            .ok()
+            .filter(|&index| index < self.sqe_entries)
+    }
+
     /// Reclaim a completed SQE back to the cache for reuse
     ///
     /// This method should be called after an operation is completed
     /// to avoid the overhead of SQE initialization for frequent operations.
+    /// Pointers outside the SQE array and SQEs already cached are ignored.
+    #[cfg(feature = "alloc")]
     pub fn reclaim_sqe(&self, sqe_ptr: *mut io_uring_sqe) {
-        // SAFETY: sqe_ptr must be a valid pointer within the SQE array
-        // and not currently in use by the kernel
-        unsafe {
-            // Reset the SQE to default values for reuse
-            (*sqe_ptr) = io_uring_sqe::default();
-            // Add to cache for later reuse
-            self.sqe_cache.borrow_mut().push(sqe_ptr);
+        let Some(index) = self.sqe_index(sqe_ptr) else {
+            return;
+        };
+        if self.sqe_cache.push(index) {
+            // SAFETY: the slot lies within the SQE array, and the caller
+            // reclaims it only once the kernel is done with it
+            unsafe { *self.sqe_at(index) = io_uring_sqe::default() };
         }
     }
 
     /// Get a cached SQE if available, or return None if cache is empty
+    #[cfg(feature = "alloc")]
     pub fn get_cached_sqe(&self) -> Option<*mut io_uring_sqe> {
-        self.sqe_cache.borrow_mut().pop()
+        self.sqe_cache.pop().map(|index| self.sqe_at(index))
     }
 
     /// Clear the SQE cache
+    #[cfg(feature = "alloc")]
     pub fn clear_sqe_cache(&self) {
-        self.sqe_cache.borrow_mut().clear();
+        self.sqe_cache.clear();
     }
 
     /// Get the number of cached SQEs
+    #[cfg(feature = "alloc")]
     pub fn cached_sqe_count(&self) -> usize {
-        self.sqe_cache.borrow().len()
+        self.sqe_cache.len() as usize
     }
 }
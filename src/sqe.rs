@@ -31,7 +31,9 @@ pub struct MsgHdr<'a> {
     pub msg_namelen: u32,
     pub msg_iov: &'a mut [Iovec],
     pub msg_control: *mut c_void,
-    pub msg_controllen: u32,
+    /// A `size_t` in the kernel's `struct msghdr`. It used to be a `u32`,
+    /// which left `msg_flags` in the upper half of the length.
+    pub msg_controllen: usize,
     pub msg_flags: i32,
 }
 
@@ -86,7 +88,7 @@ impl<'a> MsgHdr<'a> {
 
     pub fn set_control(&mut self, control: *mut c_void, controllen: u32) {
         self.msg_control = control;
-        self.msg_controllen = controllen;
+        self.msg_controllen = controllen as usize;
     }
 
     pub fn set_flags(&mut self, flags: i32) {
@@ -133,6 +135,97 @@ impl SqeFlagsBuilder {
     }
 }
 
+/// An owned, fully prepared submission queue entry
+///
+/// Produced by `build()` on any operation in this module, and pushed with
+/// [`SubmissionQueue::push`](crate::SubmissionQueue::push). Entries can be
+/// built ahead of time and submitted together.
+#[repr(transparent)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Entry(pub(crate) io_uring_sqe);
+
+impl Entry {
+    /// Set the `user_data` reported back in this entry's CQE
+    #[must_use]
+    pub fn user_data(mut self, user_data: u64) -> Self {
+        self.0.user_data = user_data;
+        self
+    }
+
+    /// Add `IOSQE_*` flags to this entry
+    ///
+    /// Flags already set by the operation are kept.
+    #[must_use]
+    pub fn flags(mut self, flags: u8) -> Self {
+        self.0.flags |= flags;
+        self
+    }
+
+    /// Run this entry with the credentials of a registered personality
+    ///
+    /// `personality` is an id from [`IoUring::register_personality`](crate::IoUring::register_personality);
+    /// 0 keeps the submitter's credentials.
+    #[must_use]
+    pub fn personality(mut self, personality: u16) -> Self {
+        self.0.personality = personality;
+        self
+    }
+
+    /// Operate on a fixed file slot instead of the entry's fd
+    ///
+    /// Sets the SQE's `fd` to the slot and adds `IOSQE_FIXED_FILE`.
+    #[must_use]
+    pub fn fixed_file(mut self, file: FixedFd) -> Self {
+        file.apply(&mut self.0);
+        self
+    }
+
+    /// The raw SQE this entry will be written as
+    #[must_use]
+    pub fn sqe(&self) -> &io_uring_sqe {
+        &self.0
+    }
+}
+
+/// A slot in the ring's registered file table
+///
+/// Handed out by [`FixedFileTable`](crate::FixedFileTable), or built from a
+/// slot number known to be filled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedFd(u32);
+
+impl FixedFd {
+    /// The slot `index`, or `None` past the largest table a ring can
+    /// register ([`IORING_MAX_FIXED_FILES`](crate::IORING_MAX_FIXED_FILES))
+    #[must_use]
+    pub const fn new(index: u32) -> Option<Self> {
+        if index < crate::IORING_MAX_FIXED_FILES {
+            Some(Self(index))
+        } else {
+            None
+        }
+    }
+
+    /// Slot number in the registered file table
+    #[must_use]
+    pub const fn index(self) -> u32 {
+        self.0
+    }
+
+    /// The value telling a direct open, accept or socket to install its file
+    /// in this slot: the slot number plus one
+    #[must_use]
+    pub const fn file_index(self) -> u32 {
+        self.0 + 1
+    }
+
+    /// Point a prepared SQE at this slot, setting `IOSQE_FIXED_FILE`
+    pub fn apply(self, sqe: &mut io_uring_sqe) {
+        sqe.fd = self.0 as i32;
+        sqe.flags |= IOSQE_FIXED_FILE;
+    }
+}
+
 pub struct Nop;
 
 impl PrepSqe for Nop {
@@ -362,6 +455,7 @@ pub struct OpenAt<'a> {
     path: &'a CStr,
     flags: u32,
     mode: u32,
+    file_index: u32,
 }
 
 impl<'a> OpenAt<'a> {
@@ -372,12 +466,22 @@ impl<'a> OpenAt<'a> {
             path,
             flags,
             mode,
+            file_index: 0,
         }
     }
 
     pub fn set_flags(&mut self, flags: u32) {
         self.flags = flags;
     }
+
+    /// Open straight into a fixed file slot instead of a regular fd
+    ///
+    /// `file_index` is [`FixedFd::file_index`], or
+    /// [`IORING_FILE_INDEX_ALLOC`](crate::IORING_FILE_INDEX_ALLOC) to let
+    /// the kernel pick the slot. `O_CLOEXEC` can't be combined with it.
+    pub fn set_file_index(&mut self, file_index: u32) {
+        self.file_index = file_index;
+    }
 }
 
 impl PrepSqe for OpenAt<'_> {
@@ -388,6 +492,7 @@ impl PrepSqe for OpenAt<'_> {
         sqe.addr = self.path.as_ptr() as u64;
         sqe.len = self.mode;
         sqe.rw_flags = self.flags as i32;
+        sqe.splice_fd_in = self.file_index as i32;
     }
 }
 
@@ -717,12 +822,23 @@ impl PrepSqe for LinkAt<'_> {
 pub struct PollAdd {
     fd: i32,
     events: u16,
+    flags: u32,
 }
 
 impl PollAdd {
     #[must_use]
     pub fn new(fd: i32, events: u16) -> Self {
-        Self { fd, events }
+        Self {
+            fd,
+            events,
+            flags: 0,
+        }
+    }
+
+    /// Keep polling after each event, posting a CQE with
+    /// `IORING_CQE_F_MORE` for every one (`IORING_POLL_ADD_MULTI`)
+    pub fn set_multishot(&mut self) {
+        self.flags |= crate::IORING_POLL_ADD_MULTI;
     }
 }
 
@@ -730,7 +846,9 @@ impl PrepSqe for PollAdd {
     fn prep(&self, sqe: &mut io_uring_sqe) {
         sqe.opcode = crate::IORING_OP_POLL_ADD;
         sqe.fd = self.fd;
-        sqe.addr = self.events as u64;
+        // `poll32_events`, which shares its slot with `rw_flags`
+        sqe.rw_flags = i32::from(self.events);
+        sqe.len = self.flags;
     }
 }
 
@@ -779,7 +897,10 @@ impl PrepSqe for Timeout<'_> {
     fn prep(&self, sqe: &mut io_uring_sqe) {
         sqe.opcode = crate::IORING_OP_TIMEOUT;
         sqe.addr = self.ts as *const crate::Timespec as u64;
-        sqe.len = self.count;
+        // The kernel takes exactly one timespec, and the completion count in
+        // `off`
+        sqe.len = 1;
+        sqe.off = u64::from(self.count);
         sqe.rw_flags = self.flags as i32;
     }
 }
@@ -818,6 +939,8 @@ impl PrepSqe for LinkTimeout<'_> {
     fn prep(&self, sqe: &mut io_uring_sqe) {
         sqe.opcode = crate::IORING_OP_LINK_TIMEOUT;
         sqe.addr = self.ts as *const crate::Timespec as u64;
+        // The kernel takes exactly one timespec
+        sqe.len = 1;
         sqe.rw_flags = self.flags as i32;
     }
 }
@@ -870,6 +993,40 @@ impl PrepSqeMut for Recv<'_> {
     }
 }
 
+/// Multishot receive into buffers picked from a provided buffer group
+///
+/// Each CQE carries `IORING_CQE_F_BUFFER` and the id of the buffer the data
+/// landed in above `IORING_CQE_BUFFER_SHIFT`. The operation ends, without
+/// `IORING_CQE_F_MORE`, on error or when the group runs out of buffers
+/// (`-ENOBUFS`).
+pub struct RecvMulti {
+    fd: i32,
+    buf_group: u16,
+    flags: i32,
+}
+
+impl RecvMulti {
+    #[must_use]
+    pub fn new(fd: i32, buf_group: u16, flags: i32) -> Self {
+        Self {
+            fd,
+            buf_group,
+            flags,
+        }
+    }
+}
+
+impl PrepSqe for RecvMulti {
+    fn prep(&self, sqe: &mut io_uring_sqe) {
+        sqe.opcode = crate::IORING_OP_RECV;
+        sqe.fd = self.fd;
+        sqe.rw_flags = self.flags;
+        sqe.ioprio = crate::IORING_RECV_MULTISHOT;
+        sqe.flags |= IOSQE_BUFFER_SELECT;
+        sqe.buf_index = self.buf_group;
+    }
+}
+
 pub struct SendMsg<'a> {
     fd: i32,
     msg: &'a MsgHdr<'a>,
@@ -924,6 +1081,7 @@ pub struct Accept<'a> {
     addrlen: Option<*mut u32>,
     flags: i32,
     file_index: u32,
+    ioprio: u16,
 }
 
 impl<'a> Accept<'a> {
@@ -935,6 +1093,7 @@ impl<'a> Accept<'a> {
             addrlen: None,
             flags,
             file_index: 0,
+            ioprio: 0,
         }
     }
 
@@ -946,6 +1105,7 @@ impl<'a> Accept<'a> {
             addrlen: Some(addrlen as *mut u32),
             flags,
             file_index: 0,
+            ioprio: 0,
         }
     }
 
@@ -957,6 +1117,7 @@ impl<'a> Accept<'a> {
             addrlen: None,
             flags,
             file_index,
+            ioprio: 0,
         }
     }
 
@@ -974,8 +1135,17 @@ impl<'a> Accept<'a> {
             addrlen: Some(addrlen as *mut u32),
             flags,
             file_index,
+            ioprio: 0,
         }
     }
+
+    /// Keep accepting, posting a CQE with `IORING_CQE_F_MORE` for every
+    /// connection (`IORING_ACCEPT_MULTISHOT`)
+    ///
+    /// The address buffer, if any, is overwritten by each connection.
+    pub fn set_multishot(&mut self) {
+        self.ioprio |= crate::IORING_ACCEPT_MULTISHOT;
+    }
 }
 
 impl PrepSqeMut for Accept<'_> {
@@ -1000,6 +1170,44 @@ impl PrepSqeMut for Accept<'_> {
 
         sqe.len = 0;
         sqe.rw_flags = self.flags;
+        sqe.ioprio = self.ioprio;
+        sqe.splice_fd_in = self.file_index as i32;
+    }
+}
+
+/// Create a socket (`IORING_OP_SOCKET`, Linux 5.19+)
+pub struct Socket {
+    domain: i32,
+    ty: i32,
+    protocol: i32,
+    file_index: u32,
+}
+
+impl Socket {
+    #[must_use]
+    pub fn new(domain: i32, ty: i32, protocol: i32) -> Self {
+        Self {
+            domain,
+            ty,
+            protocol,
+            file_index: 0,
+        }
+    }
+
+    /// Create the socket straight into a fixed file slot, as for
+    /// [`OpenAt::set_file_index`]; `SOCK_CLOEXEC` can't be combined with it
+    pub fn set_file_index(&mut self, file_index: u32) {
+        self.file_index = file_index;
+    }
+}
+
+impl PrepSqe for Socket {
+    fn prep(&self, sqe: &mut io_uring_sqe) {
+        sqe.opcode = crate::IORING_OP_SOCKET;
+        sqe.fd = self.domain;
+        sqe.off = self.ty as u64;
+        sqe.len = self.protocol as u32;
+        sqe.rw_flags = 0;
         sqe.splice_fd_in = self.file_index as i32;
     }
 }
@@ -0,0 +1,798 @@
+use core::ffi::c_void;
+use core::ptr::{null, null_mut};
+use core::sync::atomic::{fence, AtomicU32, Ordering};
+
+use rustix::fd::{AsFd, BorrowedFd, OwnedFd};
+use rustix::io::Errno;
+use rustix::io_uring::{
+    self, io_uring_files_update, io_uring_params, sigset_t, IoringEnterFlags, IoringRegisterFlags,
+    IoringRegisterOp, IoringRsrcFlags,
+};
+
+use crate::err::{EnterError, InitError};
+use crate::io_uring::{Probe, PROBE_OPS};
+use crate::rsrc::RsrcTag;
+use crate::sq::SubmissionQueue;
+use crate::Iovec;
+
+/// Handle for entering the ring and registering resources
+///
+/// Obtained from [`IoUring::submitter`](crate::IoUring::submitter) or
+/// [`IoUring::split`](crate::IoUring::split). Every method takes `&self`, so a
+/// single submitter can be shared between the thread producing submissions
+/// and the thread reaping completions.
+///
+/// If the ring fd was registered with
+/// [`IoUring::register_ring_fd`](crate::IoUring::register_ring_fd), every
+/// enter from the registering thread goes through the registered index
+/// instead. Registered ring fds live in a per-thread table, so other threads
+/// keep using the real fd, and fail with `EBADF` once it was closed. Without
+/// the `std` feature a submitter can't tell threads apart and always uses the
+/// real fd; only the ring's own methods use the registered index then.
+pub struct Submitter<'a> {
+    // `None` once the real fd was closed after registering it
+    fd: Option<&'a OwnedFd>,
+    ring_index: Option<RingIndex>,
+    // Whether the ring itself made this submitter for its own use. The ring
+    // is neither `Send` nor `Sync`, so it's on the registering thread.
+    on_ring_thread: bool,
+    // Bumped on every registration and unregistration of the buffer table,
+    // so a `FixedBufferPool` can tell whether the table is still its own
+    buffer_table: &'a AtomicU32,
+    params: &'a io_uring_params,
+    sq_khead: *const AtomicU32,
+    sq_ktail: *const AtomicU32,
+    sq_kflags: *const AtomicU32,
+}
+
+// SAFETY: the raw pointers refer to the kernel's SQ head, tail and flags, which live
+// in the ring mapping owned by the `IoUring` this submitter borrows from. They
+// are only accessed atomically, so sharing them between threads is sound. The
+// registered ring index is only used from the thread it was registered with.
+unsafe impl Send for Submitter<'_> {}
+unsafe impl Sync for Submitter<'_> {}
+
+/// Index of a registered ring fd, along with the thread it was registered
+/// with
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RingIndex {
+    index: u32,
+    #[cfg(feature = "std")]
+    thread: u64,
+}
+
+impl RingIndex {
+    /// An index just registered by the calling thread
+    pub(crate) fn new(index: u32) -> Self {
+        Self {
+            index,
+            #[cfg(feature = "std")]
+            thread: current_thread(),
+        }
+    }
+
+    pub(crate) fn index(self) -> u32 {
+        self.index
+    }
+
+    /// The index, if the calling thread is the one that registered it
+    ///
+    /// In any other thread the same index refers to that thread's own
+    /// table, i.e. to nothing or to another ring.
+    #[cfg(feature = "std")]
+    pub(crate) fn get(self) -> Option<u32> {
+        (current_thread() == self.thread).then_some(self.index)
+    }
+
+    /// Without `std` threads can't be told apart, so the calling thread is
+    /// never known to be the registering one
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn get(self) -> Option<u32> {
+        None
+    }
+}
+
+// Identifies the calling thread among all threads the process ever ran.
+// Neither TLS addresses nor tids would do, as both are reused once a thread
+// exits.
+#[cfg(feature = "std")]
+fn current_thread() -> u64 {
+    static NEXT_ID: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+    std::thread_local!(static ID: u64 = NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    ID.with(|id| *id)
+}
+
+impl<'a> Submitter<'a> {
+    pub(crate) fn new(
+        fd: Option<&'a OwnedFd>,
+        ring_index: Option<RingIndex>,
+        on_ring_thread: bool,
+        buffer_table: &'a AtomicU32,
+        params: &'a io_uring_params,
+        sq: &SubmissionQueue,
+    ) -> Self {
+        Self {
+            fd,
+            ring_index,
+            on_ring_thread,
+            buffer_table,
+            params,
+            sq_khead: sq.khead_ptr(),
+            sq_ktail: sq.ktail_ptr(),
+            sq_kflags: sq.kflags_ptr(),
+        }
+    }
+
+    /// The registered ring index, if usable from the calling thread
+    fn registered_index(&self) -> Option<u32> {
+        let ring_index = self.ring_index?;
+        if self.on_ring_thread {
+            Some(ring_index.index())
+        } else {
+            ring_index.get()
+        }
+    }
+
+    /// The fd to pass to `io_uring_enter`, and the flag marking it as a
+    /// registered index if it is one
+    ///
+    /// Fails with `EBADF` in threads other than the registering one once the
+    /// real fd was closed.
+    fn enter_fd(&self) -> Result<(BorrowedFd<'_>, IoringEnterFlags), Errno> {
+        match (self.registered_index(), self.fd) {
+            // SAFETY: with `IORING_ENTER_REGISTERED_RING` the kernel treats the
+            // value as an index into the registered ring table, not as an fd.
+            (Some(index), _) => Ok((
+                unsafe { BorrowedFd::borrow_raw(index as i32) },
+                IoringEnterFlags::REGISTERED_RING,
+            )),
+            (None, Some(fd)) => Ok((fd.as_fd(), IoringEnterFlags::empty())),
+            (None, None) => Err(Errno::BADF),
+        }
+    }
+
+    /// Number of SQEs published to the kernel but not yet consumed by it
+    fn sq_pending(&self) -> u32 {
+        // SAFETY: see the `Send`/`Sync` impls above.
+        let (head, tail) = unsafe {
+            (
+                (*self.sq_khead).load(Ordering::Acquire),
+                (*self.sq_ktail).load(Ordering::Acquire),
+            )
+        };
+        tail.wrapping_sub(head)
+    }
+
+    fn is_sqpoll(&self) -> bool {
+        self.params.flags.bits() & crate::IORING_SETUP_SQPOLL != 0
+    }
+
+    /// Whether the SQ poll thread went idle and has to be woken up
+    fn sq_need_wakeup(&self) -> bool {
+        // The tail store must be visible before we read the flags, otherwise
+        // the thread may go to sleep without having seen the new entries
+        // while we still read the old flags.
+        fence(Ordering::SeqCst);
+        // SAFETY: see the `Send`/`Sync` impls above.
+        unsafe { (*self.sq_kflags).load(Ordering::Relaxed) & crate::IORING_SQ_NEED_WAKEUP != 0 }
+    }
+
+    /// Whether CQEs are backlogged in the kernel
+    fn cq_overflow(&self) -> bool {
+        // SAFETY: see the `Send`/`Sync` impls above.
+        unsafe { (*self.sq_kflags).load(Ordering::Acquire) & crate::IORING_SQ_CQ_OVERFLOW != 0 }
+    }
+
+    /// Whether completions may be held back until the ring is entered with
+    /// `IORING_ENTER_GETEVENTS`
+    ///
+    /// That's the case for backlogged CQEs, for pending task work flagged
+    /// with `IORING_SQ_TASKRUN`, and always with `IORING_SETUP_DEFER_TASKRUN`
+    /// unless `IORING_SETUP_TASKRUN_FLAG` tells us when work is pending.
+    pub(crate) fn cq_needs_enter(&self) -> bool {
+        let setup = self.params.flags.bits();
+        if setup & crate::IORING_SETUP_DEFER_TASKRUN != 0
+            && setup & crate::IORING_SETUP_TASKRUN_FLAG == 0
+        {
+            return true;
+        }
+
+        // SAFETY: see the `Send`/`Sync` impls above.
+        let flags = unsafe { (*self.sq_kflags).load(Ordering::Acquire) };
+        flags & (crate::IORING_SQ_CQ_OVERFLOW | crate::IORING_SQ_TASKRUN) != 0
+    }
+
+    /// Enter the ring for submission, unless an SQ poll thread is awake and
+    /// nothing has to be waited for or flushed
+    fn submit_inner(&self, wait_count: u32, mut flags: u32) -> Result<usize, EnterError> {
+        let to_submit = self.sq_pending();
+
+        // Backlogged CQEs are only flushed into the ring, and deferred task
+        // work only run, on GETEVENTS.
+        let cq_needs_enter = self.cq_needs_enter();
+        if cq_needs_enter {
+            flags |= crate::IORING_ENTER_GETEVENTS;
+        }
+
+        if self.is_sqpoll() {
+            if self.sq_need_wakeup() {
+                flags |= crate::IORING_ENTER_SQ_WAKEUP;
+            } else if wait_count == 0 && !cq_needs_enter {
+                return Ok(to_submit as usize);
+            }
+        }
+
+        match self.enter(to_submit, wait_count, flags, None) {
+            Err(EnterError::SyscallFailed(rustix::io::Errno::BUSY)) if self.cq_overflow() => {
+                Err(EnterError::CqOverflow)
+            }
+            result => result,
+        }
+    }
+
+    /// Submit all SQEs published with [`SubmissionQueue::sync`]
+    ///
+    /// With `IORING_SETUP_SQPOLL` this only enters the kernel if the poll
+    /// thread needs a wakeup.
+    ///
+    /// ## Errors
+    /// Returns `EnterError` if the submit fails.
+    pub fn submit(&self) -> Result<usize, EnterError> {
+        self.submit_inner(0, 0)
+    }
+
+    /// Submit all published SQEs and wait for `wait_count` CQEs
+    ///
+    /// ## Errors
+    /// Returns `EnterError` if the submit fails.
+    pub fn submit_and_wait(&self, wait_count: usize) -> Result<usize, EnterError> {
+        self.submit_inner(wait_count as u32, crate::IORING_ENTER_GETEVENTS)
+    }
+
+    /// Wait until the SQ poll thread consumed entries from a full SQ
+    ///
+    /// Only meaningful with `IORING_SETUP_SQPOLL`; otherwise the kernel
+    /// returns immediately.
+    ///
+    /// ## Errors
+    /// Returns `EnterError` if the enter fails.
+    pub fn squeue_wait(&self) -> Result<usize, EnterError> {
+        self.enter(0, 0, crate::IORING_ENTER_SQ_WAIT, None)
+    }
+
+    #[doc = "Enter the io_uring with the specified parameters."]
+    #[doc = ""]
+    #[doc = "## Errors"]
+    #[doc = "Returns `EnterError` if the enter fails."]
+    pub fn enter(
+        &self,
+        to_submit: u32,
+        wait_count: u32,
+        flags: u32,
+        sig: Option<&sigset_t>,
+    ) -> Result<usize, EnterError> {
+        let sigmask_ptr: *mut sigset_t =
+            sig.map_or(null_mut(), |s| s as *const sigset_t as *mut sigset_t);
+        let sigmask_size = sig.map_or(0, |_| core::mem::size_of::<sigset_t>());
+
+        let (fd, fd_flags) = self.enter_fd()?;
+
+        // SAFETY: io_uring_enter is safe to call with these parameters
+        let submitted: u32 = unsafe {
+            rustix::io_uring::io_uring_enter(
+                fd,
+                to_submit,
+                wait_count,
+                IoringEnterFlags::from_bits(flags).unwrap_or(IoringEnterFlags::empty()) | fd_flags,
+                sigmask_ptr.cast::<core::ffi::c_void>(),
+                sigmask_size,
+            )
+        }?;
+
+        Ok(submitted as usize)
+    }
+
+    /// Enter the io_uring with extended arguments
+    ///
+    /// This method supports extended enter arguments when `IORING_FEAT_EXT_ARG`
+    /// feature is available. Use this for timeout with extended args.
+    ///
+    /// ## Errors
+    /// Returns `EnterError` if the enter fails.
+    pub fn enter_ext_arg(
+        &self,
+        to_submit: u32,
+        wait_count: u32,
+        flags: u32,
+        arg: &crate::io_uring_getevents_arg,
+        sig: Option<&sigset_t>,
+    ) -> Result<usize, EnterError> {
+        // Only allow extended args if feature is supported
+        if self.params.features.bits() & crate::IORING_FEAT_EXT_ARG == 0 {
+            return Err(EnterError::UnsupportedOperation);
+        }
+
+        let sigmask_size = sig.map_or(0, |_| core::mem::size_of::<sigset_t>());
+
+        let (fd, fd_flags) = self.enter_fd()?;
+
+        // SAFETY: io_uring_enter is safe to call with these parameters
+        let submitted: u32 = unsafe {
+            rustix::io_uring::io_uring_enter(
+                fd,
+                to_submit,
+                wait_count,
+                IoringEnterFlags::from_bits_retain(flags | crate::IORING_ENTER_EXT_ARG) | fd_flags,
+                arg as *const crate::io_uring_getevents_arg as *mut core::ffi::c_void,
+                sigmask_size,
+            )
+        }?;
+
+        Ok(submitted as usize)
+    }
+
+    pub(crate) fn register(
+        &self,
+        opcode: IoringRegisterOp,
+        arg: *const c_void,
+        nr_args: u32,
+    ) -> Result<u32, InitError> {
+        // SAFETY: `io_uring_register` doesn't retain `arg`; it only reads it
+        // for the duration of the syscall.
+        let result = match (self.fd, self.registered_index()) {
+            (Some(fd), _) => unsafe { io_uring::io_uring_register(fd, opcode, arg, nr_args) },
+            // Without the real fd, registration has to go through the
+            // registered index as well (Linux 6.3+).
+            (None, Some(index)) => unsafe {
+                io_uring::io_uring_register_with(
+                    BorrowedFd::borrow_raw(index as i32),
+                    opcode,
+                    IoringRegisterFlags::USE_REGISTERED_RING,
+                    arg,
+                    nr_args,
+                )
+            },
+            // The ring is only reachable from the registering thread
+            (None, None) => Err(Errno::BADF),
+        };
+        if result.is_ok()
+            && matches!(
+                opcode,
+                IoringRegisterOp::RegisterBuffers
+                    | IoringRegisterOp::RegisterBuffers2
+                    | IoringRegisterOp::UnregisterBuffers
+            )
+        {
+            self.buffer_table.fetch_add(1, Ordering::AcqRel);
+        }
+        result.map_err(InitError::RegisterFailed)
+    }
+
+    pub fn register_buffers(&self, iovecs: &[Iovec]) -> Result<(), InitError> {
+        if iovecs.is_empty() {
+            return Err(InitError::InvalidParameters);
+        }
+
+        let nr_args: u32 = iovecs
+            .len()
+            .try_into()
+            .map_err(|_| InitError::InvalidParameters)?;
+
+        self.register(
+            IoringRegisterOp::RegisterBuffers,
+            iovecs.as_ptr() as *const c_void,
+            nr_args,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn unregister_buffers(&self) -> Result<(), InitError> {
+        self.register(IoringRegisterOp::UnregisterBuffers, null(), 0)?;
+        Ok(())
+    }
+
+    pub fn register_files(&self, fds: &[i32]) -> Result<(), InitError> {
+        if fds.is_empty() {
+            return Err(InitError::InvalidParameters);
+        }
+
+        let nr_args: u32 = fds
+            .len()
+            .try_into()
+            .map_err(|_| InitError::InvalidParameters)?;
+
+        self.register(
+            IoringRegisterOp::RegisterFiles,
+            fds.as_ptr() as *const c_void,
+            nr_args,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn unregister_files(&self) -> Result<(), InitError> {
+        self.register(IoringRegisterOp::UnregisterFiles, null(), 0)?;
+        Ok(())
+    }
+
+    /// Register a provided buffer ring (Linux 5.19+)
+    ///
+    /// See [`BufRing`](crate::BufRing) for a managed ring.
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed` if the kernel rejects the ring.
+    pub fn register_pbuf_ring(&self, reg: &crate::PbufRingReg) -> Result<(), InitError> {
+        self.register(
+            IoringRegisterOp::RegisterPbufRing,
+            (reg as *const crate::PbufRingReg).cast::<c_void>(),
+            1,
+        )?;
+        Ok(())
+    }
+
+    /// Unregister the provided buffer ring of group `bgid`
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed` if no ring is registered for it.
+    pub fn unregister_pbuf_ring(&self, bgid: u16) -> Result<(), InitError> {
+        let reg = crate::PbufRingReg {
+            bgid,
+            ..Default::default()
+        };
+        self.register(
+            IoringRegisterOp::UnregisterPbufRing,
+            (&reg as *const crate::PbufRingReg).cast::<c_void>(),
+            1,
+        )?;
+        Ok(())
+    }
+
+    pub fn register_files_update(&self, offset: u32, fds: &[i32]) -> Result<(), InitError> {
+        if fds.is_empty() {
+            return Err(InitError::InvalidParameters);
+        }
+
+        let nr_args: u32 = fds
+            .len()
+            .try_into()
+            .map_err(|_| InitError::InvalidParameters)?;
+        let update = io_uring_files_update {
+            offset,
+            resv: 0,
+            fds: fds.as_ptr() as u64,
+        };
+
+        self.register(
+            IoringRegisterOp::RegisterFilesUpdate,
+            (&update as *const io_uring_files_update).cast::<c_void>(),
+            nr_args,
+        )?;
+
+        Ok(())
+    }
+
+    /// Register a table of `nr` empty fixed file slots (Linux 5.19+)
+    ///
+    /// Slots are filled with [`register_files_update`](Self::register_files_update)
+    /// or by direct descriptor operations.
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed` if the kernel rejects the table,
+    /// e.g. with `EBUSY` when the ring already has one.
+    pub fn register_files_sparse(&self, nr: u32) -> Result<(), InitError> {
+        let reg = io_uring::io_uring_rsrc_register {
+            nr,
+            flags: IoringRsrcFlags::REGISTER_SPARSE,
+            ..Default::default()
+        };
+        self.register(
+            IoringRegisterOp::RegisterFiles2,
+            (&reg as *const io_uring::io_uring_rsrc_register).cast::<c_void>(),
+            core::mem::size_of::<io_uring::io_uring_rsrc_register>() as u32,
+        )?;
+        Ok(())
+    }
+
+    /// Make `IORING_FILE_INDEX_ALLOC` pick fixed file slots from
+    /// `offset..offset + len` only (Linux 6.0+)
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed` if the range doesn't fit the
+    /// registered file table.
+    pub fn register_file_alloc_range(&self, offset: u32, len: u32) -> Result<(), InitError> {
+        // `struct io_uring_file_index_range`
+        #[repr(C)]
+        struct FileIndexRange {
+            off: u32,
+            len: u32,
+            resv: u64,
+        }
+
+        let range = FileIndexRange {
+            off: offset,
+            len,
+            resv: 0,
+        };
+        self.register(
+            IoringRegisterOp::RegisterFileAllocRange,
+            (&range as *const FileIndexRange).cast::<c_void>(),
+            0,
+        )?;
+        Ok(())
+    }
+
+    /// Register buffers, each with a tag (`IORING_REGISTER_BUFFERS2`,
+    /// Linux 5.13+)
+    ///
+    /// ## Errors
+    /// Returns `InitError::InvalidParameters` if `iovecs` is empty or the
+    /// lengths differ, or `InitError::RegisterFailed` if the kernel rejects
+    /// the buffers.
+    pub fn register_buffers_tagged(
+        &self,
+        iovecs: &[Iovec],
+        tags: &[RsrcTag],
+    ) -> Result<(), InitError> {
+        self.register_tagged(
+            IoringRegisterOp::RegisterBuffers2,
+            iovecs.as_ptr().cast::<c_void>(),
+            iovecs.len(),
+            tags,
+        )
+    }
+
+    /// Register files, each with a tag (`IORING_REGISTER_FILES2`, Linux 5.13+)
+    ///
+    /// ## Errors
+    /// As for [`register_buffers_tagged`](Self::register_buffers_tagged).
+    pub fn register_files_tagged(&self, fds: &[i32], tags: &[RsrcTag]) -> Result<(), InitError> {
+        self.register_tagged(
+            IoringRegisterOp::RegisterFiles2,
+            fds.as_ptr().cast::<c_void>(),
+            fds.len(),
+            tags,
+        )
+    }
+
+    fn register_tagged(
+        &self,
+        opcode: IoringRegisterOp,
+        data: *const c_void,
+        len: usize,
+        tags: &[RsrcTag],
+    ) -> Result<(), InitError> {
+        if len == 0 || len != tags.len() {
+            return Err(InitError::InvalidParameters);
+        }
+        let reg = io_uring::io_uring_rsrc_register {
+            nr: len.try_into().map_err(|_| InitError::InvalidParameters)?,
+            data: data as u64,
+            tags: tags.as_ptr() as u64,
+            ..Default::default()
+        };
+        self.register(
+            opcode,
+            (&reg as *const io_uring::io_uring_rsrc_register).cast::<c_void>(),
+            core::mem::size_of::<io_uring::io_uring_rsrc_register>() as u32,
+        )?;
+        Ok(())
+    }
+
+    /// Replace registered buffers from slot `offset` on, tagging the new ones
+    /// (`IORING_REGISTER_BUFFERS_UPDATE`)
+    ///
+    /// The tags of the buffers replaced are posted once the kernel is done
+    /// with them.
+    ///
+    /// ## Errors
+    /// As for [`register_buffers_tagged`](Self::register_buffers_tagged).
+    pub fn register_buffers_update_tagged(
+        &self,
+        offset: u32,
+        iovecs: &[Iovec],
+        tags: &[RsrcTag],
+    ) -> Result<(), InitError> {
+        self.update_tagged(
+            IoringRegisterOp::RegisterBuffersUpdate,
+            offset,
+            iovecs.as_ptr().cast::<c_void>(),
+            iovecs.len(),
+            tags,
+        )
+    }
+
+    /// Replace registered files from slot `offset` on, tagging the new ones
+    /// (`IORING_REGISTER_FILES_UPDATE2`)
+    ///
+    /// An fd of `-1` empties its slot.
+    ///
+    /// ## Errors
+    /// As for [`register_buffers_tagged`](Self::register_buffers_tagged).
+    pub fn register_files_update_tagged(
+        &self,
+        offset: u32,
+        fds: &[i32],
+        tags: &[RsrcTag],
+    ) -> Result<(), InitError> {
+        self.update_tagged(
+            IoringRegisterOp::RegisterFilesUpdate2,
+            offset,
+            fds.as_ptr().cast::<c_void>(),
+            fds.len(),
+            tags,
+        )
+    }
+
+    fn update_tagged(
+        &self,
+        opcode: IoringRegisterOp,
+        offset: u32,
+        data: *const c_void,
+        len: usize,
+        tags: &[RsrcTag],
+    ) -> Result<(), InitError> {
+        if len == 0 || len != tags.len() {
+            return Err(InitError::InvalidParameters);
+        }
+        let update = io_uring::io_uring_rsrc_update2 {
+            offset,
+            data: data as u64,
+            tags: tags.as_ptr() as u64,
+            nr: len.try_into().map_err(|_| InitError::InvalidParameters)?,
+            ..Default::default()
+        };
+        self.register(
+            opcode,
+            (&update as *const io_uring::io_uring_rsrc_update2).cast::<c_void>(),
+            core::mem::size_of::<io_uring::io_uring_rsrc_update2>() as u32,
+        )?;
+        Ok(())
+    }
+
+    /// Limit what the ring may be used for (Linux 5.10+)
+    ///
+    /// Only allowed once, on a ring created with
+    /// [`SetupBuilder::disabled`](crate::SetupBuilder::disabled) and not
+    /// enabled yet. See [`RestrictionSet`](crate::RestrictionSet) for a
+    /// builder.
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed` with `EBADFD` if the ring is
+    /// enabled, `EBUSY` if restrictions were already registered, or
+    /// `EINVAL` for an unknown restriction.
+    pub fn register_restrictions(
+        &self,
+        restrictions: &[crate::Restriction],
+    ) -> Result<(), InitError> {
+        let nr_args: u32 = restrictions
+            .len()
+            .try_into()
+            .map_err(|_| InitError::InvalidParameters)?;
+        self.register(
+            IoringRegisterOp::RegisterRestrictions,
+            restrictions.as_ptr().cast::<c_void>(),
+            nr_args,
+        )?;
+        Ok(())
+    }
+
+    /// Enable a ring created with [`SetupBuilder::disabled`](crate::SetupBuilder::disabled),
+    /// enforcing any registered restrictions from then on
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed(Errno::BADFD)` if the ring is
+    /// already enabled.
+    pub fn enable_rings(&self) -> Result<(), InitError> {
+        self.register(IoringRegisterOp::RegisterEnableRings, null(), 0)?;
+        Ok(())
+    }
+
+    /// Set the io-wq worker limits, storing the previous ones in `workers`
+    /// (Linux 5.15+)
+    ///
+    /// Zero fields are left unchanged, so all zeroes only reads the limits.
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed` if the kernel rejects the limits.
+    pub fn register_iowq_max_workers(
+        &self,
+        workers: &mut crate::IowqMaxWorkers,
+    ) -> Result<(), InitError> {
+        self.register(
+            IoringRegisterOp::RegisterIowqMaxWorkers,
+            (workers as *mut crate::IowqMaxWorkers).cast::<c_void>(),
+            2,
+        )?;
+        Ok(())
+    }
+
+    /// Restrict the ring's io-wq workers to `cpus` (Linux 5.14+)
+    ///
+    /// With `IORING_SETUP_SQPOLL` this applies to the SQ thread's workers.
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed` with `EINVAL` if `cpus` holds no
+    /// CPU the workers may run on.
+    pub fn register_iowq_aff(&self, cpus: &crate::CpuSet) -> Result<(), InitError> {
+        self.register(
+            IoringRegisterOp::RegisterIowqAff,
+            (cpus as *const crate::CpuSet).cast::<c_void>(),
+            core::mem::size_of::<crate::CpuSet>() as u32,
+        )?;
+        Ok(())
+    }
+
+    /// Let the ring's io-wq workers run on any CPU again
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed` if the kernel rejects the call.
+    pub fn unregister_iowq_aff(&self) -> Result<(), InitError> {
+        self.register(IoringRegisterOp::UnregisterIowqAff, null(), 0)?;
+        Ok(())
+    }
+
+    /// Register the calling thread's credentials as a personality
+    /// (Linux 5.6+)
+    ///
+    /// Returns the id to pass to [`Entry::personality`](crate::sqe::Entry::personality)
+    /// for entries that should run with these credentials, whichever thread
+    /// submits them.
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed` if the kernel has no id left.
+    pub fn register_personality(&self) -> Result<u16, InitError> {
+        let id = self.register(IoringRegisterOp::RegisterPersonality, null(), 0)?;
+        // Ids are allocated below `u16::MAX`
+        Ok(id as u16)
+    }
+
+    /// Drop a personality registered with [`register_personality`](Self::register_personality)
+    ///
+    /// Entries already submitted with it keep its credentials.
+    ///
+    /// ## Errors
+    /// Returns `InitError::RegisterFailed(Errno::INVAL)` if no personality
+    /// has this id.
+    pub fn unregister_personality(&self, id: u16) -> Result<(), InitError> {
+        self.register(
+            IoringRegisterOp::UnregisterPersonality,
+            null(),
+            u32::from(id),
+        )?;
+        Ok(())
+    }
+
+    pub fn register_eventfd(&self, eventfd: i32) -> Result<(), InitError> {
+        self.register(
+            IoringRegisterOp::RegisterEventfd,
+            (&eventfd as *const i32).cast::<c_void>(),
+            1,
+        )?;
+        Ok(())
+    }
+
+    pub fn unregister_eventfd(&self) -> Result<(), InitError> {
+        self.register(IoringRegisterOp::UnregisterEventfd, null(), 0)?;
+        Ok(())
+    }
+
+    pub fn register_eventfd_async(&self, eventfd: i32) -> Result<(), InitError> {
+        self.register(
+            IoringRegisterOp::RegisterEventfdAsync,
+            (&eventfd as *const i32).cast::<c_void>(),
+            1,
+        )?;
+        Ok(())
+    }
+
+    pub fn probe(&self) -> Result<Probe, InitError> {
+        let mut probe = Probe::new();
+        let nr_args = PROBE_OPS as u32;
+        let arg = &mut probe as *mut Probe as *const c_void;
+
+        self.register(IoringRegisterOp::RegisterProbe, arg, nr_args)?;
+
+        Ok(probe)
+    }
+}
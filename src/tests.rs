@@ -186,6 +186,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_opcode_conversions() {
+        use crate::Opcode;
+
+        for opcode in Opcode::ALL {
+            assert_eq!(Opcode::try_from(u8::from(opcode)).ok(), Some(opcode));
+        }
+        assert_eq!(Opcode::try_from(IORING_OP_NOP).ok(), Some(Opcode::Nop));
+        assert_eq!(u8::from(Opcode::SendZc), crate::IORING_OP_SEND_ZC);
+        assert_eq!(Opcode::ReadFixed.name(), "READ_FIXED");
+        assert!(Opcode::try_from(crate::IORING_OP_SENDMSG_ZC + 1).is_err());
+        assert!(Opcode::try_from(u8::MAX).is_err());
+    }
+
+    #[test]
+    fn test_probe_is_cached() {
+        use crate::Opcode;
+
+        let ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let Ok(probe) = ring.probe() else {
+            return;
+        };
+        let again = ring.probe().expect("Cached probe failed");
+        assert!(core::ptr::eq(probe, again));
+
+        assert!(probe
+            .op_flags(IORING_OP_NOP)
+            .contains(rustix::io_uring::IoringOpFlags::SUPPORTED));
+        assert!(probe.is_supported(Opcode::Nop));
+        assert!(ring.supports(Opcode::Nop));
+
+        let supported: Vec<_> = probe.supported_ops().collect();
+        assert!(supported.contains(&Opcode::Nop));
+        assert!(supported.iter().all(|&op| u8::from(op) <= probe.last_op()));
+        for opcode in Opcode::ALL {
+            assert_eq!(supported.contains(&opcode), probe.is_supported(opcode));
+        }
+
+        // Clones keep opcodes rustix has no `IoringOp` variant for
+        let copy = probe.clone();
+        assert_eq!(copy.last_op(), probe.last_op());
+        for opcode in 0..=probe.last_op() {
+            assert_eq!(copy.op_flags(opcode), probe.op_flags(opcode));
+        }
+        assert_eq!(
+            core::mem::size_of::<crate::io_uring::Probe>(),
+            core::mem::size_of::<rustix::io_uring::io_uring_probe>()
+                + crate::io_uring::PROBE_OPS
+                    * core::mem::size_of::<rustix::io_uring::io_uring_probe_op>()
+        );
+    }
+
     #[test]
     fn test_sq_space() {
         let ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
@@ -434,7 +486,11 @@ mod tests {
         let sqe = ring.poll_add(fd, events).expect("Failed to get SQE");
         assert_eq!(sqe.opcode, crate::IORING_OP_POLL_ADD);
         assert_eq!(sqe.fd, fd);
-        assert_eq!(sqe.addr, events as u64);
+        // The kernel reads the events from `poll32_events` and rejects a
+        // non-zero `addr`
+        assert_eq!(sqe.rw_flags, i32::from(events));
+        assert_eq!(sqe.addr, 0);
+        assert_eq!(sqe.len, 0);
     }
 
     #[test]
@@ -457,7 +513,8 @@ mod tests {
 
         let sqe = ring.timeout(&ts, count, flags).expect("Failed to get SQE");
         assert_eq!(sqe.opcode, crate::IORING_OP_TIMEOUT);
-        assert_eq!(sqe.len, count);
+        assert_eq!(sqe.len, 1);
+        assert_eq!(sqe.off, u64::from(count));
         assert_eq!(sqe.rw_flags, flags as i32);
         assert!(sqe.addr != 0); // Should be pointer to timespec
     }
@@ -470,7 +527,8 @@ mod tests {
 
         let sqe = ring.timeout_relative(&ts).expect("Failed to get SQE");
         assert_eq!(sqe.opcode, crate::IORING_OP_TIMEOUT);
-        assert_eq!(sqe.len, 0);
+        assert_eq!(sqe.len, 1);
+        assert_eq!(sqe.off, 0);
         assert_eq!(sqe.rw_flags, 0); // No flags for relative
         assert!(sqe.addr != 0);
     }
@@ -483,11 +541,33 @@ mod tests {
 
         let sqe = ring.timeout_absolute(&ts).expect("Failed to get SQE");
         assert_eq!(sqe.opcode, crate::IORING_OP_TIMEOUT);
-        assert_eq!(sqe.len, 0);
+        assert_eq!(sqe.len, 1);
+        assert_eq!(sqe.off, 0);
         assert_eq!(sqe.rw_flags, crate::IORING_TIMEOUT_ABS as i32);
         assert!(sqe.addr != 0);
     }
 
+    #[test]
+    fn test_timeout_fires_on_count() {
+        use crate::PrepSqe;
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let ts = crate::Timespec::new(10, 0);
+        let timeout = crate::sqe::Timeout::new(&ts, 1, 0).build().user_data(1);
+        ring.push(&timeout).expect("Failed to push entry");
+        ring.push(&crate::sqe::Nop.build().user_data(2))
+            .expect("Failed to push entry");
+        ring.submit_and_wait(2).expect("Failed to submit");
+
+        let mut results: Vec<_> = ring
+            .completions()
+            .map(|cqe| (cqe.user_data, cqe.res))
+            .collect();
+        results.sort_unstable();
+        // One completion satisfies the count long before the timespec runs out
+        assert_eq!(results, [(1, 0), (2, 0)]);
+    }
+
     #[test]
     fn test_timeout_remove_prep() {
         let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
@@ -888,9 +968,24 @@ mod tests {
         assert!(true); // If we got here, both SQE calls succeeded
     }
 
+    #[test]
+    fn test_errors_convert_to_io_error() {
+        let err: std::io::Error = crate::EnterError::SyscallFailed(Errno::BADF).into();
+        assert_eq!(err.raw_os_error(), Some(Errno::BADF.raw_os_error()));
+
+        let err: std::io::Error = InitError::RegisterFailed(Errno::INVAL).into();
+        assert_eq!(err.raw_os_error(), Some(Errno::INVAL.raw_os_error()));
+
+        let err: std::io::Error = InitError::InvalidParameters.into();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        let err: std::io::Error = crate::PushError.into();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+    }
+
     #[test]
     fn test_error_handling_invalid_fd() {
-        let mut ring = IoUring::with_entries(2, 2).expect("Failed to create ring");
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
 
         // Test with invalid file descriptor - should still prepare SQE but fail at execution
         let invalid_fd = -1;
@@ -924,7 +1019,7 @@ mod tests {
 
     #[test]
     fn test_offset_operations() {
-        let mut ring = IoUring::with_entries(2, 2).expect("Failed to create ring");
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
 
         let temp_file = NamedTempFile::new().expect("Failed to create temp file");
         let fd = temp_file.as_raw_fd();
@@ -1116,7 +1211,8 @@ mod tests {
             .timeout(&ts, count, flags)
             .expect("Failed to get timeout SQE");
         assert_eq!(sqe.opcode, crate::IORING_OP_TIMEOUT);
-        assert_eq!(sqe.len, count);
+        assert_eq!(sqe.len, 1);
+        assert_eq!(sqe.off, u64::from(count));
         assert_eq!(sqe.rw_flags, flags as i32);
         assert_ne!(sqe.addr, 0);
     }
@@ -1154,7 +1250,7 @@ mod tests {
         let poll_sqe = ring.poll_add(fd, events).expect("Failed to get poll SQE");
         assert_eq!(poll_sqe.opcode, crate::IORING_OP_POLL_ADD);
         assert_eq!(poll_sqe.fd, fd);
-        assert_eq!(poll_sqe.addr, events as u64);
+        assert_eq!(poll_sqe.rw_flags, i32::from(events));
 
         // Remove the poll operation (using the user_data from the poll)
         let user_data = poll_sqe.user_data;
@@ -1382,6 +1478,1807 @@ mod tests {
         assert_eq!(ring.available_user_data_count(), 0);
     }
 
+    #[test]
+    fn test_user_data_slab() {
+        let ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        let capacity = ring.user_data_capacity();
+        assert!(capacity >= 4);
+
+        let values: Vec<u64> = (0..capacity)
+            .map(|_| ring.alloc_user_data().expect("allocator ran dry early"))
+            .collect();
+        assert!(!values.contains(&0));
+        assert_eq!(ring.alloc_user_data(), None);
+        assert_eq!(ring.allocated_user_data_count(), capacity);
+
+        ring.free_user_data(values[1]);
+        // Double frees, zero and values never handed out are ignored
+        ring.free_user_data(values[1]);
+        ring.free_user_data(0);
+        ring.free_user_data(u64::MAX);
+        assert_eq!(ring.available_user_data_count(), 1);
+        assert_eq!(ring.allocated_user_data_count(), capacity - 1);
+
+        assert_eq!(ring.alloc_user_data(), Some(values[1]));
+        assert_eq!(ring.available_user_data_count(), 0);
+    }
+
+    #[test]
+    fn test_token_routes_completions_and_detects_stale() {
+        use crate::{OpSlab, PrepSqe, Token};
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        let mut ops: OpSlab<&str> = OpSlab::with_capacity(2);
+
+        let first = ops.insert(7, "first").expect("slab full");
+        assert_ne!(first.user_data(), 0);
+        assert_eq!(first.kind(), 7);
+        assert_eq!(Token::from_user_data(first.user_data()), first);
+
+        ring.push(&crate::sqe::Nop.build().user_data(first.user_data()))
+            .expect("SQ full");
+        ring.submit_and_wait(1).expect("submit failed");
+        let user_data = ring.peek_cqe().expect("no CQE").user_data;
+        ring.completion().advance(1);
+        let token = Token::from_user_data(user_data);
+        assert_eq!(ops.get(token), Some(&"first"));
+
+        // Retire the operation and reuse its slot for another one
+        assert_eq!(ops.remove(token), Some("first"));
+        let second = ops.insert(7, "second").expect("slab full");
+        assert_eq!(second.index(), first.index());
+        assert_ne!(second.generation(), first.generation());
+
+        // A late completion for the first operation is stale
+        assert!(!ops.contains(first));
+        assert_eq!(ops.get(first), None);
+        assert_eq!(ops.remove(first), None);
+        assert_eq!(ops.get(second), Some(&"second"));
+
+        // A token naming the right slot and generation but another kind is
+        // stale too
+        let forged = Token::from_user_data(second.user_data() ^ (1 << 56));
+        assert_eq!(ops.get(forged), None);
+
+        ops.insert(1, "third").expect("slab full");
+        assert_eq!(ops.insert(1, "fourth"), Err("fourth"));
+        assert_eq!(ops.len(), 2);
+    }
+
+    #[test]
+    fn test_owned_write_then_read() {
+        use crate::owned::{Read, Write};
+        use crate::{OwnedOps, Reaped};
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let fd = temp_file.as_raw_fd();
+
+        let mut writes = OwnedOps::with_capacity(4);
+        let token = writes
+            .push(ring.submission(), Write::new(fd, b"owned".to_vec(), 0))
+            .unwrap_or_else(|_| panic!("SQ full"));
+        assert_eq!(writes.in_flight(), 1);
+        ring.submit_and_wait(1).expect("submit failed");
+
+        let Some(Reaped::Done(done)) = writes.reap(ring.completion()) else {
+            panic!("CQE not routed");
+        };
+        assert_eq!(done.token, token);
+        assert_eq!(done.result, 5);
+        assert_eq!(done.output, b"owned");
+        assert_eq!(writes.in_flight(), 0);
+        assert!(writes.reap(ring.completion()).is_none());
+
+        let mut reads = OwnedOps::with_capacity(4);
+        let buf: Box<[u8]> = vec![0; 5].into_boxed_slice();
+        reads
+            .push(ring.submission(), Read::new(fd, buf, 0))
+            .unwrap_or_else(|_| panic!("SQ full"));
+        ring.submit_and_wait(1).expect("submit failed");
+
+        let Some(Reaped::Done(done)) = reads.reap(ring.completion()) else {
+            panic!("CQE not routed");
+        };
+        assert_eq!(done.result, 5);
+        assert_eq!(&*done.output, b"owned");
+    }
+
+    #[test]
+    fn test_owned_ops_only_reap_their_ring() {
+        use crate::owned::Timeout;
+        use crate::{OwnedOps, PrepSqe, Reaped};
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        let mut other = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        let mut timeouts = OwnedOps::with_capacity(2);
+        let ts = crate::Timespec {
+            tv_sec: 0,
+            tv_nsec: 1_000_000,
+        };
+        let token = timeouts
+            .push(ring.submission(), Timeout::relative(ts))
+            .unwrap_or_else(|_| panic!("SQ full"));
+        // Operations in flight tie the set to their ring
+        assert!(timeouts
+            .push(other.submission(), Timeout::relative(ts))
+            .is_err());
+
+        // A CQE of another ring carrying the token isn't the operation's
+        other
+            .push(&crate::sqe::Nop.build().user_data(token.user_data()))
+            .expect("Failed to push entry");
+        other.submit_and_wait(1).expect("submit failed");
+        assert!(matches!(
+            timeouts.reap(other.completion()),
+            Some(Reaped::Other(cqe)) if cqe.user_data == token.user_data()
+        ));
+        assert_eq!(timeouts.in_flight(), 1);
+
+        ring.submit_and_wait(1).expect("submit failed");
+        let Some(Reaped::Done(done)) = timeouts.reap(ring.completion()) else {
+            panic!("CQE not routed");
+        };
+        assert_eq!(done.token, token);
+        assert_eq!(done.result, -Errno::TIME.raw_os_error());
+
+        // Once idle, the set may move on to another ring
+        timeouts
+            .push(other.submission(), Timeout::relative(ts))
+            .unwrap_or_else(|_| panic!("SQ full"));
+        other.submit_and_wait(1).expect("submit failed");
+        assert!(matches!(
+            timeouts.reap(other.completion()),
+            Some(Reaped::Done(_))
+        ));
+    }
+
+    #[test]
+    fn test_owned_timeout_cancel_returns_timespec() {
+        use crate::owned::Timeout;
+        use crate::{OwnedOps, Reaped};
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        let mut timeouts = OwnedOps::with_capacity(1);
+        let ts = crate::Timespec {
+            tv_sec: 10,
+            tv_nsec: 0,
+        };
+        let token = timeouts
+            .push(ring.submission(), Timeout::relative(ts))
+            .unwrap_or_else(|_| panic!("SQ full"));
+        assert!(timeouts
+            .push(ring.submission(), Timeout::relative(ts))
+            .is_err());
+
+        assert!(timeouts.cancel(ring.submission(), token).expect("SQ full"));
+        ring.submit_and_wait(2).expect("submit failed");
+
+        let mut returned = None;
+        while let Some(reaped) = timeouts.reap(ring.completion()) {
+            match reaped {
+                Reaped::Done(done) => {
+                    assert_eq!(done.result, -Errno::CANCELED.raw_os_error());
+                    returned = Some(done.output);
+                }
+                Reaped::Other(cqe) => assert_eq!(cqe.user_data, 0),
+            }
+        }
+        let returned = returned.expect("timeout never completed");
+        assert_eq!(returned.tv_sec, 10);
+        assert!(!timeouts.cancel(ring.submission(), token).expect("SQ full"));
+    }
+
+    #[test]
+    fn test_owned_sendmsg_recvmsg() {
+        use crate::owned::{MsgHdr, RecvMsg, SendMsg};
+        use crate::{OwnedOps, Reaped};
+        use std::os::unix::net::UnixStream;
+
+        // The header is handed to the kernel as a `struct msghdr`
+        assert_eq!(core::mem::size_of::<crate::MsgHdr>(), 56);
+
+        let (tx, rx) = UnixStream::pair().expect("socketpair failed");
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+
+        let mut sends = OwnedOps::with_capacity(1);
+        let msg = MsgHdr::new(vec![b"hel".to_vec(), b"lo".to_vec()]);
+        sends
+            .push(ring.submission(), SendMsg::new(tx.as_raw_fd(), msg, 0))
+            .unwrap_or_else(|_| panic!("SQ full"));
+        ring.submit_and_wait(1).expect("submit failed");
+        let Some(Reaped::Done(sent)) = sends.reap(ring.completion()) else {
+            panic!("CQE not routed");
+        };
+        assert_eq!(sent.result, 5);
+        assert_eq!(sent.output.bufs().len(), 2);
+
+        let mut recvs = OwnedOps::with_capacity(1);
+        let msg = MsgHdr::new(vec![vec![0; 8]]);
+        recvs
+            .push(ring.submission(), RecvMsg::new(rx.as_raw_fd(), msg, 0))
+            .unwrap_or_else(|_| panic!("SQ full"));
+        ring.submit_and_wait(1).expect("submit failed");
+        let Some(Reaped::Done(received)) = recvs.reap(ring.completion()) else {
+            panic!("CQE not routed");
+        };
+        assert_eq!(received.result, 5);
+        assert_eq!(received.output.flags(), 0);
+        assert_eq!(&received.output.into_bufs()[0][..5], b"hello");
+    }
+
+    #[test]
+    fn test_chain_propagates_cancellation() {
+        use crate::PrepSqe;
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let bad_write = crate::sqe::Write::new(-1, b"x", 0).build();
+
+        let mut handle = ring
+            .chain()
+            .push(bad_write)
+            .push(crate::sqe::Nop.build())
+            .push(crate::sqe::Nop.build())
+            .submit()
+            .expect("chain failed");
+        handle
+            .wait(&mut ring, |cqe| panic!("stray CQE {}", cqe.user_data))
+            .expect("wait failed");
+
+        let canceled = -Errno::CANCELED.raw_os_error();
+        assert_eq!(
+            handle.results(),
+            &[
+                Some(-Errno::BADF.raw_os_error()),
+                Some(canceled),
+                Some(canceled)
+            ]
+        );
+        assert_eq!(handle.failed_at(), Some(0));
+        assert_eq!(handle.timeout_result(), None);
+
+        let mut handle = ring
+            .chain()
+            .push(bad_write)
+            .push(crate::sqe::Nop.build())
+            .hardlinked()
+            .submit()
+            .expect("chain failed");
+        handle.wait(&mut ring, |_| {}).expect("wait failed");
+        assert_eq!(
+            handle.results(),
+            &[Some(-Errno::BADF.raw_os_error()), Some(0)]
+        );
+    }
+
+    #[test]
+    fn test_chain_link_timeout() {
+        use crate::{PrepSqe, PrepSqeMut};
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let efd = eventfd(0, EventfdFlags::CLOEXEC).expect("eventfd failed");
+
+        // The eventfd is never written, so the read blocks until the
+        // timeout fires
+        let mut buf = [0u8; 8];
+        let mut handle = ring
+            .chain()
+            .push(crate::sqe::Nop.build())
+            .push(crate::sqe::Read::new(efd.as_raw_fd(), &mut buf, 0).build())
+            .with_timeout(crate::Timespec {
+                tv_sec: 0,
+                tv_nsec: 10_000_000,
+            })
+            .submit()
+            .expect("chain failed");
+        handle.wait(&mut ring, |_| {}).expect("wait failed");
+
+        assert_eq!(
+            handle.results(),
+            &[Some(0), Some(-Errno::CANCELED.raw_os_error())]
+        );
+        assert_eq!(handle.timeout_result(), Some(-Errno::TIME.raw_os_error()));
+        assert_eq!(handle.failed_at(), Some(1));
+    }
+
+    #[test]
+    fn test_chain_timeout_outlives_handle() {
+        use crate::{PrepSqe, PrepSqeMut};
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let efd = eventfd(0, EventfdFlags::CLOEXEC).expect("eventfd failed");
+
+        let mut buf = [0u8; 8];
+        let handle = ring
+            .chain()
+            .push(crate::sqe::Read::new(efd.as_raw_fd(), &mut buf, 0).build())
+            .with_timeout(crate::Timespec {
+                tv_sec: 0,
+                tv_nsec: 10_000_000,
+            })
+            .queue()
+            .expect("chain failed");
+        drop(handle);
+        // Freed memory would likely be handed out again here, with a
+        // timespec the kernel rejects
+        let invalid: Vec<_> = (0..8)
+            .map(|_| Box::new(crate::Timespec::new(0, -1)))
+            .collect();
+
+        ring.submit_and_wait(2).expect("Failed to submit");
+        let mut results: Vec<_> = ring.completions().map(|cqe| cqe.res).collect();
+        results.sort_unstable();
+        let mut expected = [-Errno::CANCELED.raw_os_error(), -Errno::TIME.raw_os_error()];
+        expected.sort_unstable();
+        assert_eq!(results, expected);
+        drop(invalid);
+    }
+
+    #[test]
+    fn test_chain_is_never_split() {
+        use crate::PrepSqe;
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        ring.push(&crate::sqe::Nop.build()).expect("SQ full");
+
+        let result = ring
+            .chain()
+            .push(crate::sqe::Nop.build())
+            .push(crate::sqe::Nop.build())
+            .push(crate::sqe::Nop.build())
+            .with_timeout(crate::Timespec {
+                tv_sec: 1,
+                tv_nsec: 0,
+            })
+            .queue();
+        assert!(matches!(result, Err(crate::ChainError::QueueFull)));
+        assert_eq!(ring.sq_space_left(), 3);
+    }
+
+    #[test]
+    fn test_chain_rejects_entries_without_cqes() {
+        use crate::PrepSqe;
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        let result = ring
+            .chain()
+            .with_timeout(crate::Timespec {
+                tv_sec: 1,
+                tv_nsec: 0,
+            })
+            .queue();
+        assert!(matches!(result, Err(crate::ChainError::Empty)));
+
+        let result = ring
+            .chain()
+            .push(crate::sqe::Nop.build())
+            .push(crate::sqe::Nop.build().flags(crate::IOSQE_CQE_SKIP_SUCCESS))
+            .queue();
+        assert!(matches!(result, Err(crate::ChainError::SkipSuccess)));
+        assert_eq!(ring.sq_space_left(), 4);
+    }
+
+    #[test]
+    fn test_chain_user_data_is_its_own() {
+        use crate::PrepSqe;
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+
+        // Handles dropped before their CQEs arrive use nothing up
+        for _ in 0..8 {
+            let handle = ring
+                .chain()
+                .push(crate::sqe::Nop.build())
+                .push(crate::sqe::Nop.build())
+                .submit()
+                .expect("chain failed");
+            drop(handle);
+            ring.submit_and_wait(2).expect("Failed to wait");
+            assert_eq!(ring.completions().count(), 2);
+        }
+
+        // A CQE carrying a small user_data of the caller's isn't the
+        // chain's, nor is a late one of an earlier chain
+        ring.push(&crate::sqe::Nop.build().user_data(1))
+            .expect("Failed to push entry");
+        let stale = ring
+            .chain()
+            .push(crate::sqe::Nop.build())
+            .queue()
+            .expect("chain failed");
+        drop(stale);
+        let mut handle = ring
+            .chain()
+            .push(crate::sqe::Nop.build())
+            .queue()
+            .expect("chain failed");
+        let mut other = Vec::new();
+        handle
+            .wait(&mut ring, |cqe| other.push(cqe.user_data))
+            .expect("wait failed");
+        assert_eq!(handle.results(), &[Some(0)]);
+        while other.len() < 2 {
+            ring.submit_and_wait(1).expect("Failed to wait");
+            other.extend(ring.completions().map(|cqe| cqe.user_data));
+        }
+        assert_eq!(other.len(), 2);
+        assert!(other.contains(&1));
+    }
+
+    #[test]
+    fn test_accept_multishot_accepts_every_connection() {
+        use std::net::{TcpListener, TcpStream};
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind failed");
+        let addr = listener.local_addr().expect("no local addr");
+
+        let sqe = ring
+            .accept_multishot(listener.as_raw_fd(), 0)
+            .expect("SQ full");
+        assert_eq!(sqe.ioprio, crate::IORING_ACCEPT_MULTISHOT);
+        sqe.user_data = 1;
+        ring.submit().expect("submit failed");
+
+        let _clients: Vec<TcpStream> = (0..3)
+            .map(|_| TcpStream::connect(addr).expect("connect failed"))
+            .collect();
+
+        let mut accepted = 0;
+        while accepted < 3 {
+            ring.submit_and_wait(1).expect("wait failed");
+            while let Some(cqe) = ring.peek_cqe().copied() {
+                ring.completion().advance(1);
+                if cqe.res == -Errno::INVAL.raw_os_error() {
+                    // Multishot accept needs Linux 5.19
+                    return;
+                }
+                assert!(cqe.res >= 0, "accept failed: {}", cqe.res);
+                assert!(ring.cqe_has_more(&cqe));
+                // SAFETY: the accepted fd is ours and used nowhere else
+                unsafe { rustix::io::close(cqe.res) };
+                accepted += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn test_poll_multishot_until_cancelled() {
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let efd = eventfd(0, EventfdFlags::CLOEXEC).expect("eventfd failed");
+
+        let sqe = ring
+            .poll_add_multishot(efd.as_raw_fd(), POLLIN)
+            .expect("SQ full");
+        assert_eq!(sqe.len, crate::IORING_POLL_ADD_MULTI);
+        sqe.user_data = 7;
+        ring.submit().expect("submit failed");
+
+        rustix::io::write(&efd, &1u64.to_ne_bytes()).expect("eventfd write failed");
+        ring.submit_and_wait(1).expect("wait failed");
+        let cqe = *ring.peek_cqe().expect("no CQE");
+        ring.completion().advance(1);
+        assert_eq!(cqe.user_data, 7);
+        assert!(cqe.res > 0);
+        assert!(ring.cqe_has_more(&cqe));
+
+        ring.cancel_multishot(7).expect("SQ full").user_data = 8;
+        ring.submit_and_wait(2).expect("wait failed");
+        let mut last = None;
+        while let Some(cqe) = ring.peek_cqe().copied() {
+            ring.completion().advance(1);
+            if cqe.user_data == 7 {
+                last = Some(cqe);
+            }
+        }
+        let last = last.expect("poll never ended");
+        assert!(!ring.cqe_has_more(&last));
+        assert_eq!(last.res, -Errno::CANCELED.raw_os_error());
+
+        // A cancelled operation isn't re-armed
+        let entry = {
+            use crate::PrepSqe;
+            let mut poll = crate::sqe::PollAdd::new(efd.as_raw_fd(), POLLIN);
+            poll.set_multishot();
+            poll.build().user_data(7)
+        };
+        assert!(!ring.rearm_multishot(&entry, &last).expect("SQ full"));
+    }
+
+    #[test]
+    fn test_rearm_multishot_reports_errors() {
+        use crate::PrepSqe;
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        let efd = eventfd(0, EventfdFlags::CLOEXEC).expect("eventfd failed");
+        let closed = efd.as_raw_fd();
+        drop(efd);
+
+        let entry = {
+            let mut poll = crate::sqe::PollAdd::new(closed, POLLIN);
+            poll.set_multishot();
+            poll.build().user_data(3)
+        };
+        ring.push(&entry).expect("SQ full");
+        ring.submit_and_wait(1).expect("wait failed");
+        let cqe = *ring.peek_cqe().expect("no CQE");
+        ring.completion().advance(1);
+        assert_eq!(cqe.res, -Errno::BADF.raw_os_error());
+
+        assert!(matches!(
+            ring.rearm_multishot(&entry, &cqe),
+            Err(crate::RearmError::Failed(Errno::BADF))
+        ));
+        assert_eq!(ring.submission().update_kernel_tail(), 0);
+    }
+
+    #[test]
+    fn test_recv_multishot_rearm_after_enobufs() {
+        use crate::PrepSqe;
+        use std::io::Write as _;
+        use std::os::unix::net::UnixStream;
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let (mut tx, rx) = UnixStream::pair().expect("socketpair failed");
+        let bgid = 3;
+
+        // Provide a single 16-byte buffer, id 5, laid out by hand per the
+        // kernel ABI: count in `fd`, first id in `off`, group in `buf_index`
+        let mut buf = vec![0u8; 16];
+        let sqe = ring.get_sqe().expect("SQ full");
+        sqe.opcode = crate::IORING_OP_PROVIDE_BUFFERS;
+        sqe.fd = 1;
+        sqe.addr = buf.as_mut_ptr() as u64;
+        sqe.len = 16;
+        sqe.off = 5;
+        sqe.buf_index = bgid;
+        sqe.user_data = 1;
+        ring.submit_and_wait(1).expect("provide failed");
+        let cqe = *ring.peek_cqe().expect("no CQE");
+        ring.completion().advance(1);
+        assert_eq!(cqe.res, 0);
+
+        let entry = crate::sqe::RecvMulti::new(rx.as_raw_fd(), bgid, 0)
+            .build()
+            .user_data(2);
+        assert_eq!(entry.sqe().ioprio, crate::IORING_RECV_MULTISHOT);
+        assert_ne!(entry.sqe().flags & crate::IOSQE_BUFFER_SELECT, 0);
+        ring.push(&entry).expect("SQ full");
+        ring.submit().expect("submit failed");
+
+        tx.write_all(b"first").expect("send failed");
+        ring.submit_and_wait(1).expect("wait failed");
+        let cqe = *ring.peek_cqe().expect("no CQE");
+        ring.completion().advance(1);
+        if cqe.res == -Errno::INVAL.raw_os_error() {
+            // Multishot recv needs Linux 6.0
+            return;
+        }
+        assert_eq!(cqe.res, 5);
+        assert_ne!(cqe.flags & crate::IORING_CQE_F_BUFFER, 0);
+        assert_eq!(cqe.flags >> crate::IORING_CQE_BUFFER_SHIFT, 5);
+        assert_eq!(&buf[..5], b"first");
+        assert!(ring.cqe_has_more(&cqe));
+        assert!(!ring.rearm_multishot(&entry, &cqe).expect("SQ full"));
+
+        // With the only buffer taken, the next message ends the operation
+        tx.write_all(b"second").expect("send failed");
+        ring.submit_and_wait(1).expect("wait failed");
+        let cqe = *ring.peek_cqe().expect("no CQE");
+        ring.completion().advance(1);
+        assert_eq!(cqe.res, -Errno::NOBUFS.raw_os_error());
+        assert!(!ring.cqe_has_more(&cqe));
+        assert!(ring.rearm_multishot(&entry, &cqe).expect("SQ full"));
+        assert_eq!(ring.submission().update_kernel_tail(), 1);
+    }
+
+    #[test]
+    fn test_buf_ring_recv_multishot_recycles() {
+        use crate::PrepSqe;
+        use std::io::Write as _;
+        use std::os::unix::net::UnixStream;
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let (mut tx, rx) = UnixStream::pair().expect("socketpair failed");
+
+        assert!(matches!(
+            crate::BufRing::new(&ring, 7, 3, 16),
+            Err(InitError::InvalidParameters)
+        ));
+        let bufs = match crate::BufRing::new(&ring, 7, 2, 16) {
+            Ok(bufs) => bufs,
+            // Buffer rings need Linux 5.19
+            Err(InitError::RegisterFailed(Errno::INVAL)) => return,
+            Err(e) => panic!("BufRing::new failed: {e:?}"),
+        };
+        assert!(matches!(
+            crate::BufRing::new(&ring, 7, 2, 16),
+            Err(InitError::RegisterFailed(Errno::EXIST))
+        ));
+
+        let entry = crate::sqe::RecvMulti::new(rx.as_raw_fd(), bufs.bgid(), 0)
+            .build()
+            .user_data(1);
+        ring.push(&entry).expect("SQ full");
+        ring.submit().expect("submit failed");
+
+        let mut recv = |msg: &[u8]| {
+            tx.write_all(msg).expect("send failed");
+            ring.submit_and_wait(1).expect("wait failed");
+            let cqe = *ring.peek_cqe().expect("no CQE");
+            ring.completion().advance(1);
+            cqe
+        };
+
+        let cqe = recv(b"one");
+        if cqe.res == -Errno::INVAL.raw_os_error() {
+            // Multishot recv needs Linux 6.0
+            return;
+        }
+        // SAFETY: the recv selected from this group, and each CQE is passed
+        // before its buffer goes back on the ring
+        let buf = unsafe { bufs.get(&cqe) }.expect("no buffer");
+        assert_eq!(buf.bid(), 0);
+        assert_eq!(&*buf, b"one");
+        assert_eq!(bufs.taken(), 1);
+        // A buffer is only handed out once until it is recycled
+        assert!(unsafe { bufs.get(&cqe) }.is_none());
+        drop(buf);
+        assert_eq!(bufs.taken(), 0);
+
+        let cqe = recv(b"two");
+        assert_eq!(unsafe { bufs.get(&cqe) }.expect("no buffer").bid(), 1);
+
+        // Both buffers have been used once, so this one only arrives because
+        // the first went back on the ring
+        let cqe = recv(b"three");
+        assert!(ring.cqe_has_more(&cqe));
+        let buf = unsafe { bufs.get(&cqe) }.expect("no buffer");
+        assert_eq!(buf.bid(), 0);
+        assert_eq!(&*buf, b"three");
+        drop(buf);
+
+        // Dropping the ring unregisters the group, freeing its id
+        drop(bufs);
+        drop(crate::BufRing::new(&ring, 7, 2, 16).expect("bgid still registered"));
+    }
+
+    #[test]
+    fn test_buf_ring_incremental_consumption() {
+        use crate::PrepSqe;
+        use std::io::Write as _;
+        use std::os::unix::net::UnixStream;
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let (mut tx, rx) = UnixStream::pair().expect("socketpair failed");
+
+        let bufs = match crate::BufRing::new_incremental(&ring, 9, 1, 64) {
+            Ok(bufs) => bufs,
+            // Incremental consumption needs Linux 6.12
+            Err(InitError::RegisterFailed(Errno::INVAL)) => return,
+            Err(e) => panic!("BufRing::new_incremental failed: {e:?}"),
+        };
+        assert!(bufs.is_incremental());
+
+        let entry = crate::sqe::RecvMulti::new(rx.as_raw_fd(), bufs.bgid(), 0)
+            .build()
+            .user_data(1);
+        ring.push(&entry).expect("SQ full");
+        ring.submit().expect("submit failed");
+
+        let mut recv = |msg: &[u8]| {
+            tx.write_all(msg).expect("send failed");
+            ring.submit_and_wait(1).expect("wait failed");
+            let cqe = *ring.peek_cqe().expect("no CQE");
+            assert!(ring.completion().cqe_has_buffer(&cqe));
+            assert_eq!(ring.completion().cqe_buffer_id(&cqe), 0);
+            let more = ring.completion().cqe_has_buf_more(&cqe);
+            ring.completion().advance(1);
+            (cqe, more)
+        };
+
+        let (cqe, more) = recv(b"abc");
+        assert!(more);
+        // SAFETY: the recv selected from this group, and each CQE is passed
+        // once, or again only after the kernel is done with its buffer
+        let first = unsafe { bufs.get(&cqe) }.expect("no buffer");
+        assert_eq!((first.offset(), &*first), (0, &b"abc"[..]));
+
+        let (cqe, more) = recv(b"defgh");
+        assert!(more);
+        let second = unsafe { bufs.get(&cqe) }.expect("no buffer");
+        assert_eq!((second.offset(), &*second), (3, &b"defgh"[..]));
+        // The kernel still holds the rest of the buffer
+        drop(first);
+        assert_eq!(bufs.taken(), 1);
+
+        // Filling the last 56 bytes uses the buffer up
+        let (cqe, more) = recv(&[b'x'; 56]);
+        assert!(!more);
+        let third = unsafe { bufs.get(&cqe) }.expect("no buffer");
+        assert_eq!((third.offset(), third.len()), (8, 56));
+        assert!(unsafe { bufs.get(&cqe) }.is_none());
+        drop(second);
+        drop(third);
+        assert_eq!(bufs.taken(), 0);
+
+        // Recycled, the buffer starts over from its beginning
+        let (cqe, more) = recv(b"again");
+        assert!(more);
+        let buf = unsafe { bufs.get(&cqe) }.expect("no buffer");
+        assert_eq!((buf.offset(), &*buf), (0, &b"again"[..]));
+    }
+
+    #[test]
+    fn test_fixed_buffer_pool_leases() {
+        use crate::owned::{ReadFixed, WriteFixed};
+        use crate::{OwnedOps, Reaped};
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let fd = temp_file.as_raw_fd();
+
+        let pool = crate::FixedBufferPool::new(&ring, 2, 100).expect("Failed to create pool");
+        assert_eq!(
+            (pool.count(), pool.available(), pool.buf_len()),
+            (2, 2, 100)
+        );
+
+        let mut out = pool.lease().expect("pool empty");
+        let mut inp = pool.lease().expect("pool empty");
+        assert_eq!((out.index(), inp.index()), (0, 1));
+        assert!(pool.lease().is_none());
+        assert_eq!(out.len(), 100);
+        assert_eq!(out.as_ptr() as usize % crate::fixed::FIXED_BUF_ALIGN, 0);
+        assert_eq!(inp.as_ptr() as usize % crate::fixed::FIXED_BUF_ALIGN, 0);
+
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        // The operations hold the leases until their CQEs
+        let mut writes = OwnedOps::with_capacity(1);
+        writes
+            .push(ring.submission(), WriteFixed::new(fd, out, 0))
+            .unwrap_or_else(|_| panic!("SQ full"));
+        ring.submit_and_wait(1).expect("write failed");
+        let Some(Reaped::Done(done)) = writes.reap(ring.completion()) else {
+            panic!("CQE not routed");
+        };
+        assert_eq!(done.result, 100);
+        let out = done.output;
+
+        let mut reads = OwnedOps::with_capacity(1);
+        reads
+            .push(ring.submission(), ReadFixed::new(fd, inp, 0))
+            .unwrap_or_else(|_| panic!("SQ full"));
+        assert_eq!(pool.available(), 0);
+        ring.submit_and_wait(1).expect("read failed");
+        let Some(Reaped::Done(done)) = reads.reap(ring.completion()) else {
+            panic!("CQE not routed");
+        };
+        assert_eq!(done.result, 100);
+        let inp = done.output;
+        assert_eq!(inp.index(), 1);
+        assert_eq!(&*inp, &*out);
+
+        drop(out);
+        assert_eq!(pool.available(), 1);
+        assert_eq!(pool.lease().expect("pool empty").index(), 0);
+
+        // An outstanding lease keeps the buffers registered
+        drop(pool);
+        assert!(matches!(
+            crate::FixedBufferPool::new(&ring, 1, 100),
+            Err(InitError::RegisterFailed(Errno::BUSY))
+        ));
+        drop(inp);
+        crate::FixedBufferPool::new(&ring, 1, 100).expect("buffers still registered");
+    }
+
+    #[test]
+    fn test_fixed_buffer_pool_leaves_replaced_table() {
+        let ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        let pool = crate::FixedBufferPool::new(&ring, 1, 100).expect("Failed to create pool");
+        let lease = pool.lease().expect("pool empty");
+        drop(pool);
+
+        // The table is swapped for the caller's own while the lease lives
+        ring.unregister_buffers().expect("unregister failed");
+        let mut own = [0u8; 64];
+        let iov = [Iovec::new(own.as_mut_ptr() as *mut c_void, own.len())];
+        ring.register_buffers(&iov).expect("register failed");
+
+        // Dropping the last lease unmaps the pool but keeps the caller's table
+        drop(lease);
+        assert!(matches!(
+            ring.register_buffers(&iov),
+            Err(InitError::RegisterFailed(Errno::BUSY))
+        ));
+        ring.unregister_buffers().expect("unregister failed");
+    }
+
+    #[test]
+    fn test_fixed_file_table_slots() {
+        use crate::{PrepSqe, PrepSqeMut};
+        use std::io::Write as _;
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let table = crate::FixedFileTable::new(&ring, 2, 2).expect("Failed to create table");
+        assert_eq!((table.size(), table.available()), (4, 2));
+        assert_eq!(table.kernel_range(), 2..4);
+
+        let last = crate::FixedFd::new(crate::IORING_MAX_FIXED_FILES - 1).expect("valid slot");
+        assert_eq!(last.file_index(), crate::IORING_MAX_FIXED_FILES);
+        assert!(crate::FixedFd::new(crate::IORING_MAX_FIXED_FILES).is_none());
+        assert!(crate::FixedFd::new(u32::MAX).is_none());
+
+        fn wait(ring: &mut IoUring, entry: crate::sqe::Entry) -> i32 {
+            ring.push(&entry).expect("SQ full");
+            ring.submit_and_wait(1).expect("wait failed");
+            let cqe = *ring.peek_cqe().expect("no CQE");
+            ring.completion().advance(1);
+            cqe.res
+        }
+
+        // A file installed by the table, read through its slot
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        temp_file.write_all(b"fixed").expect("write failed");
+        let file = table
+            .insert(&ring, temp_file.as_raw_fd())
+            .expect("insert failed");
+        assert_eq!(file.index(), 0);
+        let mut buf = [0u8; 5];
+        let entry = crate::sqe::Read::new(-1, &mut buf, 0)
+            .build()
+            .fixed_file(file);
+        assert_eq!(entry.sqe().fd, 0);
+        assert_ne!(entry.sqe().flags & crate::IOSQE_FIXED_FILE, 0);
+        assert_eq!(wait(&mut ring, entry), 5);
+        assert_eq!(&buf, b"fixed");
+
+        // A socket created straight into a reserved slot
+        let slot = table.reserve().expect("table full");
+        assert_eq!((slot.index(), table.available()), (1, 0));
+        let mut socket = crate::sqe::Socket::new(crate::AF_UNIX, crate::SOCK_STREAM, 0);
+        socket.set_file_index(slot.file_index());
+        assert_eq!(socket.build().sqe().splice_fd_in, 2);
+        if wait(&mut ring, socket.build()) == -Errno::INVAL.raw_os_error() {
+            // IORING_OP_SOCKET needs Linux 5.19
+            return;
+        }
+        assert!(matches!(
+            table.insert(&ring, temp_file.as_raw_fd()),
+            Err(InitError::RegisterFailed(Errno::NFILE))
+        ));
+
+        // Slots the kernel picks come from its own range only
+        let mut socket = crate::sqe::Socket::new(crate::AF_UNIX, crate::SOCK_STREAM, 0);
+        socket.set_file_index(crate::IORING_FILE_INDEX_ALLOC);
+        let first = wait(&mut ring, socket.build());
+        let mut open = crate::sqe::OpenAt::new(crate::AT_FDCWD, c"/dev/null", 0, 0);
+        open.set_file_index(crate::IORING_FILE_INDEX_ALLOC);
+        let second = wait(&mut ring, open.build());
+        assert_eq!((first, second), (2, 3));
+        assert_eq!(
+            wait(&mut ring, socket.build()),
+            -Errno::NFILE.raw_os_error(),
+            "kernel range should be full"
+        );
+        let opened = table.allocated(second).expect("not in the kernel range");
+        assert_eq!(opened.index(), 3);
+        assert!(table.allocated(-Errno::NFILE.raw_os_error()).is_none());
+        assert!(!table.release(opened));
+
+        // Closing frees the slot, whichever way it happens
+        table.remove(&ring, file).expect("remove failed");
+        assert_eq!(
+            wait(
+                &mut ring,
+                crate::sqe::CloseDirect::new(slot.index()).build()
+            ),
+            0
+        );
+        assert!(table.release(slot));
+        assert!(!table.release(slot));
+        assert_eq!(table.available(), 2);
+        table.remove(&ring, opened).expect("remove failed");
+    }
+
+    #[test]
+    fn test_rsrc_tag_events() {
+        use crate::{RsrcEvent, RsrcTag};
+
+        fn next_event(ring: &mut IoUring) -> Option<RsrcEvent> {
+            ring.submit_and_wait(1).expect("wait failed");
+            let cqe = *ring.peek_cqe().expect("no CQE");
+            ring.completion().advance(1);
+            assert_eq!((cqe.res, cqe.flags), (0, 0));
+            RsrcEvent::from_cqe(&cqe)
+        }
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        if !ring.has_rsrc_tags() {
+            return;
+        }
+
+        let mut bufs = [[0u8; 64]; 3];
+        let iovecs: Vec<Iovec> = bufs
+            .iter_mut()
+            .map(|buf| Iovec::new(buf.as_mut_ptr().cast(), buf.len()))
+            .collect();
+        assert!(matches!(
+            ring.register_buffers_tagged(&iovecs[..2], &[RsrcTag::buffer(10)]),
+            Err(InitError::InvalidParameters)
+        ));
+        ring.register_buffers_tagged(&iovecs[..2], &[RsrcTag::buffer(10), RsrcTag::NONE])
+            .expect("register failed");
+
+        // Replacing a buffer releases the old one
+        ring.register_buffers_update_tagged(0, &iovecs[2..], &[RsrcTag::buffer(12)])
+            .expect("update failed");
+        assert_eq!(next_event(&mut ring), Some(RsrcEvent::Buffer(10)));
+        // Untagged buffers go silently
+        ring.unregister_buffers().expect("unregister failed");
+        assert_eq!(next_event(&mut ring), Some(RsrcEvent::Buffer(12)));
+        assert!(ring.peek_cqe().is_none());
+
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        ring.register_files_tagged(
+            &[temp_file.as_raw_fd(), -1],
+            &[RsrcTag::file(1), RsrcTag::NONE],
+        )
+        .expect("register failed");
+        ring.register_files_update_tagged(1, &[temp_file.as_raw_fd()], &[RsrcTag::file(2)])
+            .expect("update failed");
+        ring.register_files_update_tagged(0, &[-1], &[RsrcTag::NONE])
+            .expect("update failed");
+        assert_eq!(next_event(&mut ring), Some(RsrcEvent::File(1)));
+        ring.unregister_files().expect("unregister failed");
+        assert_eq!(next_event(&mut ring), Some(RsrcEvent::File(2)));
+
+        // Anything outside the tag space is not an event
+        let mut cqe = crate::io_uring_cqe {
+            user_data: RsrcTag::file(7).user_data(),
+            res: 0,
+            flags: 0,
+        };
+        assert_eq!(RsrcEvent::from_cqe(&cqe), Some(RsrcEvent::File(7)));
+        cqe.user_data = 7;
+        assert_eq!(RsrcEvent::from_cqe(&cqe), None);
+        cqe.user_data = RsrcTag::buffer(7).user_data() | 1 << 40;
+        assert_eq!(RsrcEvent::from_cqe(&cqe), None);
+    }
+
+    #[test]
+    fn test_restriction_set_locks_down_ring() {
+        use crate::{IoringRegisterOp, PrepSqe, PrepSqeMut, RestrictionSet};
+
+        fn run(ring: &mut IoUring, entry: crate::sqe::Entry) -> i32 {
+            ring.push(&entry).expect("SQ full");
+            ring.submit_and_wait(1).expect("wait failed");
+            let cqe = *ring.peek_cqe().expect("no CQE");
+            ring.completion().advance(1);
+            cqe.res
+        }
+
+        let set = RestrictionSet::new()
+            .allow_op(crate::Opcode::Nop)
+            .allow_register_op(IoringRegisterOp::RegisterEventfd)
+            .allow_sqe_flags(crate::IOSQE_IO_LINK)
+            .allow_sqe_flags(crate::IOSQE_CQE_SKIP_SUCCESS);
+        let flags = set
+            .restrictions()
+            .iter()
+            .filter(|r| r.opcode == crate::IORING_RESTRICTION_SQE_FLAGS_ALLOWED)
+            .map(|r| r.arg)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            flags,
+            [crate::IOSQE_IO_LINK | crate::IOSQE_CQE_SKIP_SUCCESS]
+        );
+
+        // Restrictions only go on disabled rings
+        let ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        assert!(matches!(
+            set.apply(&ring),
+            Err(InitError::RegisterFailed(Errno::BADFD))
+        ));
+
+        let mut ring = crate::SetupBuilder::new()
+            .sq_entries(4)
+            .disabled()
+            .build()
+            .expect("Failed to create ring");
+        set.apply(&ring).expect("apply failed");
+        // Registering restrictions isn't among the allowed register ops
+        assert!(matches!(
+            set.register(&ring),
+            Err(InitError::RegisterFailed(Errno::ACCESS))
+        ));
+
+        let eacces = -Errno::ACCESS.raw_os_error();
+        assert_eq!(run(&mut ring, crate::sqe::Nop.build().user_data(1)), 0);
+        let mut buf = [0u8; 1];
+        let read = crate::sqe::Read::new(0, &mut buf, 0).build();
+        assert_eq!(run(&mut ring, read), eacces);
+        let nop = crate::sqe::Nop.build().flags(crate::IOSQE_ASYNC);
+        assert_eq!(run(&mut ring, nop), eacces);
+
+        let eventfd = rustix::event::eventfd(0, rustix::event::EventfdFlags::CLOEXEC)
+            .expect("eventfd failed");
+        ring.register_eventfd(eventfd.as_raw_fd())
+            .expect("allowed register op failed");
+        assert!(matches!(
+            ring.unregister_eventfd(),
+            Err(InitError::RegisterFailed(Errno::ACCESS))
+        ));
+    }
+
+    #[test]
+    fn test_iowq_limits_and_affinity() {
+        use crate::{CpuSet, IowqMaxWorkers};
+
+        let mut cpus = CpuSet::new();
+        assert!(cpus.set(0));
+        assert!(cpus.set(65));
+        assert!(!cpus.set(CpuSet::MAX_CPUS));
+        cpus.unset(65);
+        assert!(cpus.is_set(0) && !cpus.is_set(65));
+        assert_eq!(cpus.count(), 1);
+
+        let ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        ring.set_iowq_max_workers(4, 8).expect("set limits failed");
+        assert_eq!(
+            ring.set_iowq_max_workers(0, 0).expect("read limits failed"),
+            IowqMaxWorkers {
+                bounded: 4,
+                unbounded: 8
+            }
+        );
+        // Zero leaves a limit alone
+        let previous = ring.set_iowq_max_workers(2, 0).expect("set limits failed");
+        assert_eq!(previous.bounded, 4);
+        let current = ring.set_iowq_max_workers(0, 0).expect("read limits failed");
+        assert_eq!((current.bounded, current.unbounded), (2, 8));
+
+        ring.set_iowq_affinity(&cpus).expect("set affinity failed");
+        ring.clear_iowq_affinity().expect("clear affinity failed");
+    }
+
+    #[test]
+    fn test_register_personality() {
+        use crate::PrepSqe;
+
+        fn run(ring: &mut IoUring, personality: u16) -> i32 {
+            let nop = crate::sqe::Nop.build().personality(personality);
+            ring.push(&nop).expect("SQ full");
+            ring.submit_and_wait(1).expect("wait failed");
+            let cqe = *ring.peek_cqe().expect("no CQE");
+            ring.completion().advance(1);
+            cqe.res
+        }
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        let id = ring.register_personality().expect("register failed");
+        assert_ne!(id, 0);
+        let other = ring.register_personality().expect("register failed");
+        assert_ne!(other, id);
+
+        assert_eq!(run(&mut ring, id), 0);
+        ring.unregister_personality(id).expect("unregister failed");
+        assert_eq!(run(&mut ring, id), -Errno::INVAL.raw_os_error());
+        assert!(matches!(
+            ring.unregister_personality(id),
+            Err(InitError::RegisterFailed(Errno::INVAL))
+        ));
+        assert_eq!(run(&mut ring, other), 0);
+    }
+
+    #[test]
+    fn test_sqe_cache_reclaim() {
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+
+        let sqe = ring.get_sqe().expect("SQ full");
+        sqe.user_data = 42;
+        let sqe_ptr: *mut crate::io_uring_sqe = sqe;
+
+        // SAFETY: the SQE hasn't been submitted, so the kernel doesn't own it
+        ring.reclaim_sqe(unsafe { &mut *sqe_ptr });
+        ring.reclaim_sqe(unsafe { &mut *sqe_ptr });
+        assert_eq!(ring.submission().cached_sqe_count(), 1);
+
+        // A pointer outside the SQE array is not cached
+        let mut stray = crate::io_uring_sqe::default();
+        ring.reclaim_sqe(&mut stray);
+        assert_eq!(ring.submission().cached_sqe_count(), 1);
+
+        let reused = ring.get_sqe_with_reclaim().expect("no SQE");
+        assert!(core::ptr::eq(reused, sqe_ptr));
+        assert_eq!(reused.user_data, 0);
+        assert_eq!(ring.submission().cached_sqe_count(), 0);
+    }
+
+    #[test]
+    fn test_completions_wraparound() {
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+
+        // Push the CQ head past the end of the ring several times over
+        let mut next = 0u64;
+        for _ in 0..8 {
+            for _ in 0..3 {
+                let sqe = ring.nop().expect("Failed to get SQE");
+                sqe.user_data = next;
+                next += 1;
+            }
+            ring.submit_and_wait(3).expect("Failed to submit");
+
+            let completions = ring.completions();
+            assert_eq!(completions.len(), 3);
+            let user_data: Vec<u64> = completions.map(|cqe| cqe.user_data).collect();
+            assert_eq!(user_data, vec![next - 3, next - 2, next - 1]);
+        }
+
+        assert_eq!(ring.completions().len(), 0);
+    }
+
+    #[test]
+    fn test_completions_partial_consume() {
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+
+        for user_data in 0..4 {
+            ring.nop().expect("Failed to get SQE").user_data = user_data;
+        }
+        ring.submit_and_wait(4).expect("Failed to submit");
+
+        // Dropping the batch only releases the entries that were consumed
+        let mut completions = ring.completions();
+        assert_eq!(completions.next().map(|cqe| cqe.user_data), Some(0));
+        drop(completions);
+        assert_eq!(ring.completions().len(), 3);
+
+        let mut cqes = [crate::io_uring_cqe::default(); 2];
+        assert_eq!(ring.copy_cqes(&mut cqes), 2);
+        assert_eq!(cqes[0].user_data, 1);
+        assert_eq!(cqes[1].user_data, 2);
+
+        assert_eq!(ring.copy_cqes(&mut cqes), 1);
+        assert_eq!(cqes[0].user_data, 3);
+        assert_eq!(ring.copy_cqes(&mut cqes), 0);
+    }
+
+    #[test]
+    fn test_big_entry_layout() {
+        assert_eq!(core::mem::size_of::<crate::io_uring_sqe128>(), 128);
+        assert_eq!(core::mem::size_of::<crate::io_uring_cqe32>(), 32);
+
+        let mut sqe = crate::io_uring_sqe128::new();
+        sqe.cmd_mut()[0] = 0xAB;
+        sqe.cmd_mut()[crate::IORING_SQE128_CMD_SIZE - 1] = 0xCD;
+        assert_eq!(sqe.sqe.addr3, 0xAB);
+        assert_eq!(sqe.cmd()[crate::IORING_SQE128_CMD_SIZE - 1], 0xCD);
+    }
+
+    #[test]
+    fn test_big_entry_ring_roundtrip() {
+        let mut ring = crate::SetupBuilder::new()
+            .sq_entries(4)
+            .sqe128()
+            .cqe32()
+            .build()
+            .expect("Failed to create SQE128/CQE32 ring");
+
+        // Go around both rings twice so every slot is indexed at least once
+        for round in 0..4u64 {
+            for i in 0..4 {
+                let sqe = ring.get_sqe128().expect("Failed to get SQE128");
+                sqe.sqe.opcode = IORING_OP_NOP;
+                sqe.sqe.user_data = round * 4 + i;
+            }
+            ring.submit_and_wait(4).expect("Failed to submit");
+
+            let cqe = ring.peek_cqe32().expect("Missing CQE32");
+            assert_eq!(cqe.cqe.user_data, round * 4);
+            assert_eq!(cqe.extra1(), 0);
+            assert_eq!(cqe.extra2(), 0);
+
+            let mut completions = ring.completions();
+            for i in 0..4 {
+                let cqe = completions.next32().expect("Missing CQE32");
+                assert_eq!(cqe.cqe.user_data, round * 4 + i);
+                assert_eq!(cqe.cqe.res, 0);
+            }
+            assert!(completions.next32().is_none());
+        }
+    }
+
+    #[test]
+    fn test_big_entry_accessors_on_regular_ring() {
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+
+        assert!(ring.get_sqe128().is_none());
+        ring.nop().expect("Failed to get SQE");
+        ring.submit_and_wait(1).expect("Failed to submit");
+        assert!(ring.peek_cqe32().is_none());
+        assert!(ring.peek_cqe().is_some());
+    }
+
+    #[test]
+    fn test_entry_build_and_setters() {
+        use crate::{PrepSqe, PrepSqeMut};
+
+        let mut buf = [0u8; 16];
+        let entry = crate::sqe::Read::new(3, &mut buf, 64)
+            .build()
+            .user_data(0x42)
+            .flags(IOSQE_IO_LINK)
+            .flags(IOSQE_ASYNC)
+            .personality(7);
+
+        let sqe = entry.sqe();
+        assert_eq!(sqe.opcode, IORING_OP_READ);
+        assert_eq!(sqe.fd, 3);
+        assert_eq!(sqe.off, 64);
+        assert_eq!(sqe.len, 16);
+        assert_eq!(sqe.addr, buf.as_ptr() as u64);
+        assert_eq!(sqe.user_data, 0x42);
+        assert_eq!(sqe.flags, IOSQE_IO_LINK | IOSQE_ASYNC);
+        assert_eq!(sqe.personality, 7);
+
+        let nop = crate::sqe::Nop.build();
+        assert_eq!(nop.sqe().opcode, IORING_OP_NOP);
+        assert_eq!(nop.sqe().user_data, 0);
+    }
+
+    #[test]
+    fn test_push_multiple_and_submit() {
+        use crate::PrepSqe;
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+
+        let entries: Vec<_> = (0..3)
+            .map(|i| crate::sqe::Nop.build().user_data(i))
+            .collect();
+        ring.push_multiple(&entries)
+            .expect("Failed to push entries");
+        ring.push(&crate::sqe::Nop.build().user_data(3))
+            .expect("Failed to push entry");
+        assert!(ring.push(&crate::sqe::Nop.build()).is_err());
+
+        ring.submit_and_wait(4).expect("Failed to submit");
+        let user_data: Vec<u64> = ring.completions().map(|cqe| cqe.user_data).collect();
+        assert_eq!(user_data, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_push_multiple_is_atomic() {
+        use crate::PrepSqe;
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+
+        ring.push(&crate::sqe::Nop.build().user_data(1))
+            .expect("Failed to push entry");
+        let before = ring.sq_space_left();
+
+        let entries = [crate::sqe::Nop.build(); 4];
+        assert!(ring.push_multiple(&entries).is_err());
+        assert_eq!(ring.sq_space_left(), before);
+
+        ring.push_multiple(&entries[..3])
+            .expect("Failed to push entries");
+        assert!(ring.is_sq_full());
+    }
+
+    #[test]
+    fn test_registered_ring_fd() {
+        use crate::PrepSqe;
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        assert_eq!(ring.registered_ring_fd(), None);
+        assert!(ring.unregister_ring_fd().is_err());
+        assert!(ring.close_ring_fd().is_err());
+
+        let index = match ring.register_ring_fd() {
+            Ok(index) => index,
+            Err(crate::err::InitError::RegisterFailed(Errno::INVAL)) => return,
+            Err(e) => panic!("Failed to register ring fd: {e:?}"),
+        };
+        assert_eq!(
+            ring.register_ring_fd().expect("Failed to re-register"),
+            index
+        );
+        assert_eq!(ring.registered_ring_fd(), Some(index));
+
+        ring.push(&crate::sqe::Nop.build().user_data(1))
+            .expect("Failed to push entry");
+        ring.submit_and_wait(1).expect("Failed to submit");
+        assert_eq!(ring.completions().next().map(|cqe| cqe.user_data), Some(1));
+
+        ring.unregister_ring_fd()
+            .expect("Failed to unregister ring fd");
+        assert_eq!(ring.registered_ring_fd(), None);
+
+        ring.register_ring_fd().expect("Failed to register ring fd");
+        ring.close_ring_fd().expect("Failed to close ring fd");
+        assert_eq!(ring.as_raw_fd(), -1);
+        assert!(ring.unregister_ring_fd().is_err());
+
+        ring.push(&crate::sqe::Nop.build().user_data(2))
+            .expect("Failed to push entry");
+        ring.submit_and_wait(1).expect("Failed to submit");
+        assert_eq!(ring.completions().next().map(|cqe| cqe.user_data), Some(2));
+    }
+
+    #[test]
+    fn test_registered_ring_fd_across_threads() {
+        use crate::PrepSqe;
+
+        let mut ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        match ring.register_ring_fd() {
+            Ok(_) => {}
+            Err(crate::err::InitError::RegisterFailed(Errno::INVAL)) => return,
+            Err(e) => panic!("Failed to register ring fd: {e:?}"),
+        }
+
+        let (submitter, sq, cq) = ring.split();
+        sq.push(&crate::sqe::Nop.build().user_data(1))
+            .expect("Failed to push entry");
+        sq.sync();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                // Likely the same index as the first ring's, but in this
+                // thread's table
+                let mut other = IoUring::with_entries(4, 4).expect("Failed to create ring");
+                other
+                    .register_ring_fd()
+                    .expect("Failed to register ring fd");
+                submitter.submit_and_wait(1).expect("Failed to submit");
+                assert!(other.peek_cqe().is_none());
+            });
+        });
+        assert_eq!(cq.completions().next().map(|cqe| cqe.user_data), Some(1));
+
+        ring.close_ring_fd().expect("Failed to close ring fd");
+        let (submitter, sq, cq) = ring.split();
+        sq.push(&crate::sqe::Nop.build().user_data(2))
+            .expect("Failed to push entry");
+        sq.sync();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                assert!(matches!(
+                    submitter.submit(),
+                    Err(crate::err::EnterError::SyscallFailed(Errno::BADF))
+                ));
+            });
+        });
+        submitter.submit_and_wait(1).expect("Failed to submit");
+        assert_eq!(cq.completions().next().map(|cqe| cqe.user_data), Some(2));
+    }
+
+    #[test]
+    fn test_registered_ring_fd_after_thread_exit() {
+        use crate::PrepSqe;
+
+        // Register from a thread that exits right after, leaking the ring so
+        // its queues outlive the thread
+        let (submitter, sq, cq) = std::thread::spawn(|| {
+            let ring = Box::leak(Box::new(
+                IoUring::with_entries(8, 8).expect("Failed to create ring"),
+            ));
+            match ring.register_ring_fd() {
+                Ok(_) => Some(ring.split()),
+                Err(crate::err::InitError::RegisterFailed(Errno::INVAL)) => None,
+                Err(e) => panic!("Failed to register ring fd: {e:?}"),
+            }
+        })
+        .join()
+        .expect("Registering thread panicked")
+        .map_or((None, None, None), |(submitter, sq, cq)| {
+            (Some(submitter), Some(sq), Some(cq))
+        });
+        let (Some(submitter), Some(sq), Some(cq)) = (submitter, sq, cq) else {
+            return;
+        };
+
+        // Later threads may reuse the exited thread's stack, TLS and tid, and
+        // register another ring at the same index
+        for user_data in 1..=8 {
+            std::thread::scope(|s| {
+                s.spawn(|| {
+                    let mut other = IoUring::with_entries(4, 4).expect("Failed to create ring");
+                    other
+                        .register_ring_fd()
+                        .expect("Failed to register ring fd");
+                    sq.push(&crate::sqe::Nop.build().user_data(user_data))
+                        .expect("Failed to push entry");
+                    sq.sync();
+                    assert_eq!(submitter.submit().expect("Failed to submit"), 1);
+                    assert!(other.peek_cqe().is_none());
+                });
+            });
+            submitter.submit_and_wait(1).expect("Failed to wait");
+            assert_eq!(
+                cq.completions().next().map(|cqe| cqe.user_data),
+                Some(user_data)
+            );
+        }
+    }
+
+    #[test]
+    fn test_sqpoll_submit_and_wakeup() {
+        use crate::PrepSqe;
+
+        let mut ring = match crate::SetupBuilder::new()
+            .sq_entries(4)
+            .sqpoll()
+            .sq_thread_idle(10)
+            .build()
+        {
+            Ok(ring) => ring,
+            Err(crate::err::InitError::SyscallFailed(Errno::PERM)) => return,
+            Err(e) => panic!("Failed to create SQPOLL ring: {e:?}"),
+        };
+
+        // Let the poll thread go idle, so the next submit has to wake it
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(ring.submission().needs_flush());
+
+        ring.push(&crate::sqe::Nop.build().user_data(1))
+            .expect("Failed to push entry");
+        assert_eq!(ring.submit().expect("Failed to submit"), 1);
+        ring.submit_and_wait(1).expect("Failed to wait");
+        assert_eq!(ring.completions().next().map(|cqe| cqe.user_data), Some(1));
+
+        // Fill the SQ and wait for the poll thread to drain it
+        let entries = [crate::sqe::Nop.build(); 4];
+        ring.push_multiple(&entries)
+            .expect("Failed to push entries");
+        ring.submit().expect("Failed to submit");
+        ring.squeue_wait().expect("Failed to wait for SQ space");
+        assert!(ring.sq_space_left() > 0);
+
+        ring.submit_and_wait(4).expect("Failed to wait");
+        assert_eq!(ring.completions().count(), 4);
+    }
+
+    #[test]
+    fn test_cq_overflow_backlog_and_flush() {
+        use crate::PrepSqe;
+
+        let mut ring = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        if !ring.has_nodrop() {
+            return;
+        }
+        assert_eq!(ring.overflow_status(), crate::OverflowStatus::default());
+
+        // Twice as many completions as the CQ can hold
+        for round in 0..2u64 {
+            let entries: Vec<_> = (0..4)
+                .map(|i| crate::sqe::Nop.build().user_data(round * 4 + i))
+                .collect();
+            ring.push_multiple(&entries)
+                .expect("Failed to push entries");
+            ring.submit().expect("Failed to submit");
+        }
+
+        let status = ring.overflow_status();
+        assert!(status.backlogged);
+        assert!(status.is_lossless());
+
+        let mut user_data: Vec<u64> = ring.completions().map(|cqe| cqe.user_data).collect();
+        let status = ring.flush_overflow().expect("Failed to flush");
+        assert!(!status.backlogged);
+        user_data.extend(ring.completions().map(|cqe| cqe.user_data));
+        assert_eq!(user_data, (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_cap_inflight_at_cq_size() {
+        use crate::PrepSqe;
+
+        let mut ring = crate::SetupBuilder::new()
+            .sq_entries(4)
+            .cq_entries(4)
+            .cap_inflight()
+            .build()
+            .expect("Failed to create ring");
+
+        let entries = [crate::sqe::Nop.build(); 4];
+        ring.push_multiple(&entries)
+            .expect("Failed to push entries");
+        ring.submit_and_wait(4).expect("Failed to submit");
+
+        // The SQ is empty again, but the CQ is full
+        assert_eq!(ring.inflight(), Some(4));
+        assert!(ring.push(&entries[0]).is_err());
+        assert!(ring.get_sqe().is_none());
+
+        assert_eq!(ring.completions().take(2).count(), 2);
+        assert_eq!(ring.inflight(), Some(2));
+        ring.push_multiple(&entries[..2])
+            .expect("Failed to push entries");
+        assert!(ring.push(&entries[0]).is_err());
+        assert!(!ring.overflow_status().backlogged);
+    }
+
+    #[test]
+    fn test_cap_inflight_uneven_cqes() {
+        use crate::PrepSqe;
+        use rustix::event::{eventfd, EventfdFlags};
+
+        let mut ring = crate::SetupBuilder::new()
+            .sq_entries(4)
+            .cq_entries(4)
+            .cap_inflight()
+            .build()
+            .expect("Failed to create ring");
+        let mut uncapped = IoUring::with_entries(4, 4).expect("Failed to create ring");
+        assert_eq!(uncapped.inflight(), None);
+
+        // Entries with IOSQE_CQE_SKIP_SUCCESS post a CQE only if they fail
+        let skipped = crate::sqe::Nop.build().flags(crate::IOSQE_CQE_SKIP_SUCCESS);
+        assert!(ring.push(&skipped).is_err());
+        assert!(ring
+            .push_multiple(&[crate::sqe::Nop.build(), skipped])
+            .is_err());
+        assert_eq!(ring.sq_space_left(), 4);
+        uncapped
+            .submission()
+            .push(&skipped)
+            .expect("Failed to push entry");
+
+        // A multishot poll posts a CQE per event for its single SQE
+        let efd = eventfd(0, EventfdFlags::CLOEXEC).expect("eventfd failed");
+        let mut poll = crate::sqe::PollAdd::new(efd.as_raw_fd(), 0x1);
+        poll.set_multishot();
+        ring.push(&poll.build().user_data(7))
+            .expect("Failed to push entry");
+        for _ in 0..6 {
+            rustix::io::write(&efd, &1u64.to_ne_bytes()).expect("write failed");
+            ring.submit_and_wait(1).expect("Failed to wait");
+            let mut buf = [0u8; 8];
+            rustix::io::read(&efd, &mut buf).expect("read failed");
+            assert!(ring
+                .completions()
+                .all(|cqe| cqe.flags & crate::IORING_CQE_F_MORE != 0));
+            assert_eq!(ring.inflight(), Some(1));
+            assert_eq!(ring.sq_space_left(), 3);
+        }
+    }
+
+    #[test]
+    fn test_cap_inflight_rsrc_tags() {
+        use crate::{PrepSqeMut, RsrcEvent, RsrcTag};
+
+        let mut ring = crate::SetupBuilder::new()
+            .sq_entries(4)
+            .cq_entries(4)
+            .cap_inflight()
+            .build()
+            .expect("Failed to create ring");
+        let efd = eventfd(0, EventfdFlags::CLOEXEC).expect("eventfd failed");
+        match ring.register_files_tagged(&[efd.as_raw_fd()], &[RsrcTag::file(3)]) {
+            Ok(()) => {}
+            Err(crate::err::InitError::RegisterFailed(Errno::INVAL)) => return,
+            Err(e) => panic!("Failed to register files: {e:?}"),
+        }
+
+        // A read that stays in flight while the tag CQE comes and goes
+        let mut buf = [0u8; 8];
+        ring.push(
+            &crate::sqe::Read::new(efd.as_raw_fd(), &mut buf, 0)
+                .build()
+                .user_data(1),
+        )
+        .expect("Failed to push entry");
+        ring.submit().expect("Failed to submit");
+        assert_eq!(ring.inflight(), Some(1));
+
+        ring.unregister_files().expect("Failed to unregister files");
+        ring.submit_and_wait(1).expect("Failed to wait");
+        let cqe = ring.completions().next().expect("No tag CQE");
+        assert_eq!(RsrcEvent::from_cqe(&cqe), Some(RsrcEvent::File(3)));
+        assert_eq!(ring.inflight(), Some(1));
+        assert_eq!(ring.sq_space_left(), 3);
+
+        rustix::io::write(&efd, &1u64.to_ne_bytes()).expect("write failed");
+        ring.submit_and_wait(1).expect("Failed to wait");
+        assert_eq!(ring.completions().next().map(|cqe| cqe.user_data), Some(1));
+        assert_eq!(ring.inflight(), Some(0));
+    }
+
+    #[test]
+    fn test_defer_taskrun_requires_single_issuer() {
+        let result = crate::SetupBuilder::new()
+            .sq_entries(4)
+            .defer_taskrun()
+            .build();
+        assert!(matches!(
+            result,
+            Err(crate::err::InitError::InvalidParameters)
+        ));
+    }
+
+    #[test]
+    fn test_defer_taskrun_peek_runs_task_work() {
+        use crate::PrepSqeMut;
+
+        let mut ring = match crate::SetupBuilder::new()
+            .sq_entries(4)
+            .single_issuer()
+            .defer_taskrun()
+            .build()
+        {
+            Ok(ring) => ring,
+            Err(crate::err::InitError::SyscallFailed(Errno::INVAL)) => return,
+            Err(e) => panic!("Failed to create DEFER_TASKRUN ring: {e:?}"),
+        };
+
+        // The read only completes once the eventfd is written, from task
+        // work that the kernel defers until we enter the ring again.
+        let efd = eventfd(0, EventfdFlags::empty()).expect("Failed to create eventfd");
+        let mut buf = [0u8; 8];
+        ring.push(
+            &crate::sqe::Read::new(efd.as_raw_fd(), &mut buf, 0)
+                .build()
+                .user_data(7),
+        )
+        .expect("Failed to push entry");
+        ring.submit().expect("Failed to submit");
+        assert!(ring.peek_cqe().is_none());
+
+        rustix::io::write(&efd, &1u64.to_ne_bytes()).expect("Failed to write eventfd");
+        let cqe = ring.peek_cqe().expect("Missing CQE");
+        assert_eq!(cqe.user_data, 7);
+        assert_eq!(cqe.res, 8);
+    }
+
+    #[test]
+    fn test_defer_taskrun_copy_cqes_runs_task_work() {
+        use crate::PrepSqeMut;
+
+        let mut ring = match crate::SetupBuilder::new()
+            .sq_entries(4)
+            .single_issuer()
+            .defer_taskrun()
+            .build()
+        {
+            Ok(ring) => ring,
+            Err(crate::err::InitError::SyscallFailed(Errno::INVAL)) => return,
+            Err(e) => panic!("Failed to create DEFER_TASKRUN ring: {e:?}"),
+        };
+
+        let efd = eventfd(0, EventfdFlags::empty()).expect("Failed to create eventfd");
+        let mut buf = [0u8; 8];
+        ring.push(
+            &crate::sqe::Read::new(efd.as_raw_fd(), &mut buf, 0)
+                .build()
+                .user_data(7),
+        )
+        .expect("Failed to push entry");
+        ring.submit().expect("Failed to submit");
+        let mut cqes = [crate::io_uring_cqe::default(); 2];
+        assert_eq!(ring.copy_cqes(&mut cqes), 0);
+
+        rustix::io::write(&efd, &1u64.to_ne_bytes()).expect("Failed to write eventfd");
+        assert_eq!(ring.copy_cqes(&mut cqes), 1);
+        assert_eq!((cqes[0].user_data, cqes[0].res), (7, 8));
+    }
+
+    #[test]
+    fn test_ring_memory_single_mmap() {
+        let ring = IoUring::with_entries(8, 8).expect("Failed to create ring");
+        let memory = ring.ring_memory();
+        assert!(!memory.is_user_provided());
+        assert_eq!(memory.is_single_mmap(), ring.has_single_mmap());
+        if memory.is_single_mmap() {
+            assert_eq!(memory.sq_ring_ptr(), memory.cq_ring_ptr());
+        }
+    }
+
+    #[test]
+    fn test_no_mmap_user_memory() {
+        use crate::PrepSqe;
+        use core::ptr::NonNull;
+        use rustix::mm::{mmap_anonymous, munmap, MapFlags, ProtFlags};
+
+        let builder = crate::SetupBuilder::new().sq_entries(4).cq_entries(8);
+        let (rings_size, sqes_size) = builder.no_mmap_sizes();
+        let alloc = |size: usize| {
+            let size = size.next_multiple_of(4096);
+            let ptr = unsafe {
+                mmap_anonymous(
+                    core::ptr::null_mut(),
+                    size,
+                    ProtFlags::READ | ProtFlags::WRITE,
+                    MapFlags::PRIVATE,
+                )
+            }
+            .expect("Failed to allocate ring memory");
+            (NonNull::new(ptr.cast::<u8>()).unwrap(), size)
+        };
+        let (rings, rings_len) = alloc(rings_size);
+        let (sqes, sqes_len) = alloc(sqes_size);
+
+        let too_small = unsafe { crate::UserRingMemory::new(rings, 64, sqes, sqes_len) };
+        assert!(matches!(
+            builder.clone().no_mmap(too_small).build(),
+            Err(crate::err::InitError::InvalidParameters)
+        ));
+
+        let memory = unsafe { crate::UserRingMemory::new(rings, rings_len, sqes, sqes_len) };
+        let builder = builder.no_mmap(memory);
+        // A clone doesn't share the memory, nor quietly maps the rings instead
+        assert!(matches!(
+            builder.clone().build(),
+            Err(crate::err::InitError::InvalidParameters)
+        ));
+        match builder.build() {
+            Ok(mut ring) => {
+                assert!(ring.ring_memory().is_user_provided());
+                assert_eq!(ring.ring_memory().sq_ring_ptr(), rings.as_ptr());
+
+                for i in 0..8 {
+                    ring.push(&crate::sqe::Nop.build().user_data(i))
+                        .expect("Failed to push entry");
+                    ring.submit_and_wait(1).expect("Failed to submit");
+                    assert_eq!(ring.completions().next().map(|cqe| cqe.user_data), Some(i));
+                }
+            }
+            Err(crate::err::InitError::SyscallFailed(Errno::INVAL)) => {}
+            Err(e) => panic!("Failed to create NO_MMAP ring: {e:?}"),
+        }
+
+        unsafe {
+            munmap(rings.as_ptr().cast(), rings_len).expect("Failed to unmap");
+            munmap(sqes.as_ptr().cast(), sqes_len).expect("Failed to unmap");
+        }
+    }
+
+    #[test]
+    fn test_split_across_threads() {
+        use crate::PrepSqe;
+
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        assert_send::<crate::SubmissionQueue>();
+        assert_send::<crate::CompletionQueue>();
+        assert_sync::<crate::Submitter<'_>>();
+
+        const N: u64 = 256;
+        let mut ring = IoUring::with_entries(8, 16).expect("Failed to create ring");
+        let (submitter, sq, cq) = ring.split();
+
+        std::thread::scope(|s| {
+            let submitter = &submitter;
+            s.spawn(move || {
+                for i in 0..N {
+                    let entry = crate::sqe::Nop.build().user_data(i);
+                    while sq.push(&entry).is_err() {
+                        sq.sync();
+                        submitter.submit().expect("Failed to submit");
+                        std::thread::yield_now();
+                    }
+                }
+                sq.sync();
+                submitter.submit().expect("Failed to submit");
+            });
+
+            let mut seen = vec![false; N as usize];
+            let mut count = 0;
+            while count < N {
+                for cqe in cq.completions() {
+                    assert_eq!(cqe.res, 0);
+                    assert!(!seen[cqe.user_data as usize]);
+                    seen[cqe.user_data as usize] = true;
+                    count += 1;
+                }
+                if count < N {
+                    submitter.submit_and_wait(1).expect("Failed to wait");
+                }
+            }
+        });
+    }
+
     // Phase 8: Feature Detection and Probing Tests
 
     #[test]
@@ -1401,4 +3298,58 @@ mod tests {
         let (major, _minor, _patch) = ring.kernel_version();
         assert!(major >= 5, "Should have io_uring support");
     }
+
+    #[test]
+    fn test_parse_kernel_release() {
+        use crate::caps::parse_release;
+
+        assert_eq!(parse_release("6.8.0-45-generic"), Some((6, 8, 0)));
+        assert_eq!(
+            parse_release("5.15.153.1-microsoft-standard-WSL2"),
+            Some((5, 15, 153))
+        );
+        assert_eq!(parse_release("6.10-rc3"), Some((6, 10, 0)));
+        assert_eq!(parse_release("6.1.0+"), Some((6, 1, 0)));
+        assert_eq!(parse_release("4.19.0-25-amd64"), Some((4, 19, 0)));
+        assert_eq!(parse_release(""), None);
+        assert_eq!(parse_release("linux"), None);
+    }
+
+    #[test]
+    fn test_capabilities_matrix() {
+        use crate::Capability;
+
+        let ring = IoUring::new(8).expect("Failed to create ring");
+        let caps = ring.capabilities();
+
+        assert_eq!(caps.kernel_version(), crate::caps::kernel_version());
+        assert_eq!(caps.kernel_version(), ring.kernel_version());
+        assert_ne!(
+            caps.kernel_version(),
+            (5, 4, 0),
+            "Version is still hardcoded"
+        );
+
+        assert_eq!(caps.has(Capability::NoDrop), ring.has_nodrop());
+        assert_eq!(caps.has(Capability::ExtArg), ring.has_ext_arg());
+        assert_eq!(
+            caps.has(Capability::ExtendedFormats),
+            ring.has_extended_formats()
+        );
+        assert_eq!(
+            caps.has(Capability::RegisteredRingFd),
+            ring.kernel_version_at_least(5, 18, 0)
+        );
+
+        // Multishot recv needs both a new enough kernel and the opcode
+        if caps.has(Capability::MultishotRecv) {
+            assert!(ring.opcode_supported(crate::IORING_OP_RECV));
+            assert!(ring.kernel_version_at_least(6, 0, 0));
+        }
+
+        let supported: Vec<_> = caps.supported().collect();
+        for cap in Capability::ALL {
+            assert_eq!(supported.contains(&cap), caps.has(cap));
+        }
+    }
 }
@@ -0,0 +1,196 @@
+//! Generational `user_data` tokens.
+//!
+//! A [`Token`] packs a slot index, the slot's generation and a caller-chosen
+//! operation kind into the 64-bit `user_data` of an SQE. The slot holds the
+//! operation's state in an [`OpSlab`]. Removing the state bumps the slot's
+//! generation, so a CQE that arrives after its operation was retired, such as
+//! a multishot completion racing its cancellation, no longer matches the slot
+//! even once the slot has been reused, and is reported as stale instead of
+//! being routed to the new operation.
+
+use alloc::boxed::Box;
+
+use crate::slab::IndexList;
+
+const INDEX_BITS: u32 = 32;
+const GENERATION_BITS: u32 = 24;
+const GENERATION_MASK: u32 = (1 << GENERATION_BITS) - 1;
+
+/// An operation's identity, carried through the kernel as `user_data`
+///
+/// Bits 0-31 hold the slot index, bits 32-55 the slot generation and bits
+/// 56-63 the operation kind. Generations start at 1, so a token is never 0.
+/// Kind `0xff` overlaps [`RsrcTag`](crate::RsrcTag)s and kind `0xfe`
+/// [`Chain`](crate::Chain) entries; don't use them alongside tagged
+/// registrations or chains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Token(u64);
+
+impl Token {
+    fn new(index: u32, generation: u32, kind: u8) -> Self {
+        Self(
+            u64::from(index)
+                | u64::from(generation) << INDEX_BITS
+                | u64::from(kind) << (INDEX_BITS + GENERATION_BITS),
+        )
+    }
+
+    /// Reinterpret the `user_data` of a CQE
+    ///
+    /// Any value is accepted; whether it names a live operation is up to
+    /// the [`OpSlab`] it is looked up in.
+    #[must_use]
+    pub const fn from_user_data(user_data: u64) -> Self {
+        Self(user_data)
+    }
+
+    /// The value to put in an SQE's `user_data`
+    #[must_use]
+    pub const fn user_data(self) -> u64 {
+        self.0
+    }
+
+    /// Slot index in the [`OpSlab`]
+    #[must_use]
+    pub const fn index(self) -> u32 {
+        self.0 as u32
+    }
+
+    /// Generation of the slot when the token was issued
+    #[must_use]
+    pub const fn generation(self) -> u32 {
+        (self.0 >> INDEX_BITS) as u32 & GENERATION_MASK
+    }
+
+    /// Operation kind given to [`OpSlab::insert`]
+    #[must_use]
+    pub const fn kind(self) -> u8 {
+        (self.0 >> (INDEX_BITS + GENERATION_BITS)) as u8
+    }
+}
+
+impl From<Token> for u64 {
+    fn from(token: Token) -> Self {
+        token.0
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    kind: u8,
+    state: Option<T>,
+}
+
+/// Fixed-capacity per-operation state, addressed by [`Token`]
+///
+/// Storage is allocated once, up front; inserting and removing never touch
+/// the heap. Size it from the ring, e.g. with
+/// [`IoUring::user_data_capacity`](crate::IoUring::user_data_capacity), or
+/// larger when multishot operations keep slots busy.
+pub struct OpSlab<T> {
+    slots: Box<[Slot<T>]>,
+    free: IndexList,
+}
+
+impl<T> OpSlab<T> {
+    /// A slab holding up to `capacity` operations
+    #[must_use]
+    pub fn with_capacity(capacity: u32) -> Self {
+        let slots: Box<[Slot<T>]> = (0..capacity)
+            .map(|_| Slot {
+                generation: 1,
+                kind: 0,
+                state: None,
+            })
+            .collect();
+        let free = IndexList::new(capacity);
+        // Push in reverse so the lowest slots are handed out first
+        for index in (0..free.capacity()).rev() {
+            free.push(index);
+        }
+        Self { slots, free }
+    }
+
+    /// Maximum number of operations held at once
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.free.capacity() as usize
+    }
+
+    /// Number of operations currently held
+    #[must_use]
+    pub fn len(&self) -> usize {
+        (self.free.capacity() - self.free.len()) as usize
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Store the state of a new operation of the given kind
+    ///
+    /// ## Errors
+    /// Returns `state` back if every slot is taken.
+    pub fn insert(&mut self, kind: u8, state: T) -> Result<Token, T> {
+        let Some(index) = self.free.pop() else {
+            return Err(state);
+        };
+
+        let slot = &mut self.slots[index as usize];
+        slot.kind = kind;
+        slot.state = Some(state);
+        Ok(Token::new(index, slot.generation, kind))
+    }
+
+    fn slot(&self, token: Token) -> Option<&Slot<T>> {
+        self.slots
+            .get(token.index() as usize)
+            .filter(|slot| slot.matches(token))
+    }
+
+    /// Whether `token` names an operation still held, rather than a stale one
+    #[must_use]
+    pub fn contains(&self, token: Token) -> bool {
+        self.slot(token).is_some()
+    }
+
+    /// State of the operation `token` names, or `None` if it is stale
+    #[must_use]
+    pub fn get(&self, token: Token) -> Option<&T> {
+        self.slot(token)?.state.as_ref()
+    }
+
+    /// Mutable state of the operation `token` names, or `None` if it is stale
+    #[must_use]
+    pub fn get_mut(&mut self, token: Token) -> Option<&mut T> {
+        self.slots
+            .get_mut(token.index() as usize)
+            .filter(|slot| slot.matches(token))?
+            .state
+            .as_mut()
+    }
+
+    /// Retire the operation `token` names and return its state
+    ///
+    /// The slot's generation moves on, so `token` and every copy of it go
+    /// stale. Returns `None`, changing nothing, if `token` already was.
+    pub fn remove(&mut self, token: Token) -> Option<T> {
+        let slot = self
+            .slots
+            .get_mut(token.index() as usize)
+            .filter(|slot| slot.matches(token))?;
+
+        let state = slot.state.take();
+        // Skip 0 on wraparound so tokens stay non-zero
+        slot.generation = (slot.generation % GENERATION_MASK) + 1;
+        self.free.push(token.index());
+        state
+    }
+}
+
+impl<T> Slot<T> {
+    fn matches(&self, token: Token) -> bool {
+        self.state.is_some() && self.generation == token.generation() && self.kind == token.kind()
+    }
+}